@@ -0,0 +1,34 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// exponential retry delay with jitter, shared by the retry loops
+pub struct Backoff {
+    base_ms: u64,
+    max_ms: u64,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(base_ms: usize, max_ms: usize) -> Self {
+        Backoff {
+            base_ms: base_ms.max(1) as u64,
+            max_ms: max_ms.max(base_ms).max(1) as u64,
+            attempt: 0,
+        }
+    }
+
+    // base * 2^attempt capped to max, plus up to 1/4 of jitter
+    pub fn next_delay(&mut self) -> Duration {
+        let delay_ms = self.base_ms.saturating_mul(1u64 << self.attempt.min(32)).min(self.max_ms);
+        self.attempt += 1;
+        Duration::from_millis((delay_ms + jitter(delay_ms / 4)).min(self.max_ms))
+    }
+}
+
+// no need for a real random generator to spread retries
+fn jitter(max_ms: u64) -> u64 {
+    if max_ms == 0 {
+        return 0;
+    }
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos() as u64);
+    nanos % (max_ms + 1)
+}
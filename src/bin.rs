@@ -1,114 +1,795 @@
-use std::ffi::CString;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CString, OsString};
 use std::fmt::Debug;
+use std::fs;
 use std::io::Write;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
-use std::time::Instant;
-
-use alsa::Ctl;
-use alsa::ctl::{ElemId, ElemIface};
-use alsa::hctl::{Elem, HCtl};
-use anyhow::{anyhow, Result};
-use cancellable_timer::{Canceller, Timer};
-use clap::Parser;
-use crossbeam_channel::{Receiver, Sender, unbounded};
+use std::time::{Duration, Instant};
+
+use alsa::ctl::Ctl;
+use alsa::hctl::HCtl;
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgMatches, Command as ClapCommand, CommandFactory, FromArgMatches, Parser, ValueSource};
 use env_logger::Builder;
-use log::{debug, info, LevelFilter, trace};
+use log::{debug, info, Level, LevelFilter, trace, warn};
+use nix::sys::signal::Signal;
+
+use gaudio_ctl::{cmdline, ctl_list, ctl_lock, ctl_tlv, event_trace, executor, logging, metrics, privileges, signals, socket, watchdog};
+#[cfg(feature = "dbus")]
+use gaudio_ctl::dbus;
+use gaudio_ctl::{Direction, DirHandle, Executor, Msg, Watcher};
+use gaudio_ctl::backoff::Backoff;
+use gaudio_ctl::coupling::Coupling;
+use gaudio_ctl::builtin_loop::{LoopCfg, LoopFormat};
+use gaudio_ctl::camilla::CamillaCfg;
+use gaudio_ctl::error::Error;
+use gaudio_ctl::event_trace::EventTracer;
+use gaudio_ctl::logging::DirLevelLogger;
+use gaudio_ctl::signals::Wake;
+use gaudio_ctl::watcher::{attach_secondary, card_gone, CtlData, CtlEvent, find_elem, fits_ctl, get_ctl_data, initial_rate, RateMapping, read_rate,
+                           Ready, reconciled_rate};
+use gaudio_ctl::executor::{ChildOutput, CmdCfg, DebounceMode, ExecCfg, ExecState, ExecUpdate, Hooks, RateRanges, RateScale, SpawnCfg};
 
-use executor::{CmdCfg, ExecData};
+mod config;
+mod watch;
 
-mod executor;
+use watch::WatchSpec;
 
 #[derive(Parser, Debug)]
 #[clap(about, version, author)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Cmd>,
+
     /// Debouncing timeout in ms, 0 = no debouncing
-    #[clap(short = 'd', long, default_value_t = 50)]
+    #[clap(short = 'd', long, parse(try_from_str = parse_duration_ms), default_value_t = 50)]
     timeout: usize,
 
+    /// Debouncing strategy - fixed window of --timeout, or waiting until the rate is unchanged for --stable-ms
+    #[clap(long, arg_enum, default_value = "fixed")]
+    debounce_mode: DebounceMode,
+
+    /// Time in ms the rate must stay unchanged before the start in the stable debounce mode
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 200)]
+    stable_ms: usize,
+
+    /// Max wait in ms for a stable rate in the stable debounce mode, then the latest rate is started
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 5000)]
+    stable_max_ms: usize,
+
+    /// Stop grace period in ms, a new rate within the period cancels the stop, 0 = stop immediately
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    stop_grace_ms: usize,
+
+    /// Keepalive in ms, resuming at the same rate within the period after rate 0 keeps the exec running, 0 = no keepalive
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    keepalive_ms: usize,
+
+    /// Max run time of each exec in ms, then stopped until the next rate change, 0 = unlimited
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    max_runtime_ms: usize,
+
+    /// Exec must be alive N ms after start to be reported running, else the start failed, 0 = no check
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    confirm_ms: usize,
+
+    /// Timeout of hook commands (e.g. onstop) in ms, then killed, 0 = unlimited
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 5000)]
+    hook_timeout_ms: usize,
+
+    /// Wait up to N ms for the card and its rate ctls to appear at startup, 0 = no waiting
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    wait_for_controls_ms: usize,
+
+    /// Wait for the card and its rate ctls indefinitely
+    #[clap(long)]
+    wait_forever: bool,
+
+    /// Initial delay of retries in ms, doubled with each failed attempt
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 100)]
+    backoff_base_ms: usize,
+
+    /// Max delay of retries in ms
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 5000)]
+    backoff_max_ms: usize,
+
+    /// Report exec threads busy with a single rate change for more than N ms, exceeding its longest intended handling, 0 = no watchdog
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    watchdog_ms: usize,
+
+    /// Exit when the watchdog detects an unresponsive exec thread
+    #[clap(long, requires = "watchdog-ms")]
+    watchdog_abort: bool,
+
+    /// Delay in ms before starting to act on rate ctls, letting the card settle
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    startup_delay_ms: usize,
+
+    /// Do not act on the rates already set at startup, waiting for the next rate change
+    #[clap(long)]
+    no_initial_rates: bool,
+
+    /// Print each handled rate event as a JSON object line on stdout (timestamp, direction, rate, action, pids)
+    #[clap(long)]
+    json_events: bool,
+
+    /// Number of first ctl events discarded as warm-up
+    #[clap(long, default_value_t = 0)]
+    warmup_events: usize,
+
+    /// Wait up to N ms after a rate change for the other direction to change too, starting both together, 0 = no waiting
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0, conflicts_with = "mirror")]
+    couple_ms: usize,
+
+    /// Poll rate ctls every N ms instead of subscribing for events, 0 = use events
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    poll_interval_ms: usize,
+
+    /// Re-read the rate ctls when no event arrives within N ms while an exec runs, 0 = never
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 0)]
+    event_idle_ms: usize,
+
     /// Verbose (-v = debug, -vv = trace)
     #[clap(short, long, parse(from_occurrences))]
     verbose: u8,
 
-    /// Show start/stop timing
+    /// Log level of the playback threads (error, warn, info, debug, trace), overriding -v
+    #[clap(long)]
+    plog_level: Option<LevelFilter>,
+
+    /// Log level of the capture threads (error, warn, info, debug, trace), overriding -v
+    #[clap(long)]
+    clog_level: Option<LevelFilter>,
+
+    /// Show start latency and start/stop timing
     #[clap(short = 't', long)]
     show_timing: bool,
 
-    /// Gadget card name
+    /// Gadget card ID, name, longname or index
     #[clap(short = 'g', long, default_value = "UAC2Gadget")]
     gadget_name: String,
 
-    /// Playback Rate ctl name
+    /// Dir of the lock files preventing two instances from handling the same ctl
+    #[clap(long, default_value = "/run/lock")]
+    lock_dir: String,
+
+    /// Playback Rate ctl name, or comma-separated candidate names resolved to the first existing one
     #[clap(short = 'p', long, default_value = "Playback Rate")]
     pctl: String,
 
-    /// Capture Rate ctl name
+    /// Capture Rate ctl name, or comma-separated candidate names resolved to the first existing one
     #[clap(short = 'c', long, default_value = "Capture Rate")]
     cctl: String,
 
-    /// Playback command ({R} replaced with real rate)
-    #[clap(short = 'x', long, default_value = "alsaloop -vv -r {R} --latency=1000 -f S32_LE -S playshift -C hw:Loopback,1 -P hw:UAC2Gadget")]
-    pcmd: String,
+    /// Secondary playback Rate ctl, the nonzero rate of the two ctls is used
+    #[clap(long)]
+    pctl2: Option<String>,
+
+    /// Secondary capture Rate ctl, the nonzero rate of the two ctls is used
+    #[clap(long)]
+    cctl2: Option<String>,
+
+    /// Index of the playback ctl value with the rate, for multi-value ctls
+    #[clap(long, default_value_t = 0)]
+    pctl_index: u32,
+
+    /// Index of the capture ctl value with the rate, for multi-value ctls
+    #[clap(long, default_value_t = 0)]
+    cctl_index: u32,
+
+    /// Device of the playback ctl
+    #[clap(long, default_value_t = 0)]
+    pdevice: u32,
+
+    /// Subdevice of the playback ctl
+    #[clap(long, default_value_t = 0)]
+    psubdevice: u32,
+
+    /// Device of the capture ctl
+    #[clap(long, default_value_t = 0)]
+    cdevice: u32,
+
+    /// Subdevice of the capture ctl
+    #[clap(long, default_value_t = 0)]
+    csubdevice: u32,
+
+    /// Run playback at this rate while the ctl value is nonzero, for boolean "stream active" ctls
+    #[clap(long)]
+    pfixed_rate: Option<usize>,
+
+    /// Run capture at this rate while the ctl value is nonzero, for boolean "stream active" ctls
+    #[clap(long)]
+    cfixed_rate: Option<usize>,
+
+    /// Inverted playback ctl - value 0 runs at the fixed rate, nonzero stops
+    #[clap(long, requires = "pfixed-rate")]
+    pinvert: bool,
+
+    /// Inverted capture ctl - value 0 runs at the fixed rate, nonzero stops
+    #[clap(long, requires = "cfixed-rate")]
+    cinvert: bool,
+
+    /// Extra ctl with its own command, comma-separated keys name, label, device, subdevice, fixed-rate and cmd last, e.g. name=Capture Rate,device=1,cmd=alsaloop -r {R}
+    #[clap(long, parse(try_from_str = watch::parse_watch), multiple_occurrences = true)]
+    watch: Vec<WatchSpec>,
+
+    /// Mirror mode - rate of the MASTER direction ctl drives both directions, the other ctl is ignored
+    #[clap(long, arg_enum, value_name = "MASTER")]
+    mirror: Option<Direction>,
+
+    /// Rate of enumerated ctl item NAME=RATE, overriding the rate parsed from the item name
+    #[clap(long, parse(try_from_str = parse_key_val), multiple_occurrences = true)]
+    enum_rate_map: Vec<(String, usize)>,
+
+    /// Alias RATE=STRING replacing placeholder {RALIAS} in pcmd/ccmd, unmapped rates replace it with the rate
+    #[clap(long, parse(try_from_str = parse_key_val), multiple_occurrences = true)]
+    rate_alias: Vec<(usize, String)>,
+
+    /// Playback command ({R} replaced with real rate), repeated for a group of commands started/stopped together
+    #[clap(short = 'x', long, multiple_occurrences = true, validator = cmdline::check, default_value = "alsaloop -vv -r {R} --latency=1000 -f S32_LE -S playshift -C hw:Loopback,1 -P hw:UAC2Gadget")]
+    pcmd: Vec<String>,
+
+    /// Capture command ({R} replaced with real rate), repeated for a group of commands started/stopped together
+    #[clap(short = 'y', long, multiple_occurrences = true, validator = cmdline::check, default_value = "alsaloop -vv -r {R} --latency=1000 -f S32_LE -S captshift -C hw:UAC2Gadget -P hw:Loopback,1")]
+    ccmd: Vec<String>,
+
+    /// Playback command RATES=CMD for the comma separated rates or LOW-HIGH ranges, replacing pcmd for them, repeated for a group
+    #[clap(long, value_name = "RATES=CMD", parse(try_from_str = parse_rate_cmd), multiple_occurrences = true)]
+    prate_cmd: Vec<(RateRanges, String)>,
+
+    /// Capture command RATES=CMD for the comma separated rates or LOW-HIGH ranges, replacing ccmd for them, repeated for a group
+    #[clap(long, value_name = "RATES=CMD", parse(try_from_str = parse_rate_cmd), multiple_occurrences = true)]
+    crate_cmd: Vec<(RateRanges, String)>,
+
+    /// Playback command as literal args, repeated for each arg without splitting, the first is the executable. Overrides pcmd
+    #[clap(long, multiple_occurrences = true, allow_hyphen_values = true)]
+    parg: Vec<String>,
+
+    /// Capture command as literal args, repeated for each arg without splitting, the first is the executable. Overrides ccmd
+    #[clap(long, multiple_occurrences = true, allow_hyphen_values = true)]
+    carg: Vec<String>,
+
+    /// Command run after stopping the playback exec ({R} and {PREV_R} replaced with the stopped rate)
+    #[clap(long, validator = cmdline::check)]
+    p_onstop: Option<String>,
+
+    /// Command run after stopping the capture exec ({R} and {PREV_R} replaced with the stopped rate)
+    #[clap(long, validator = cmdline::check)]
+    c_onstop: Option<String>,
+
+    /// Command run after a failed playback start or an exit of the running exec with --restart-on-exit ({R} replaced with the rate, {ERR} with the error or exit status)
+    #[clap(long, validator = cmdline::check)]
+    p_onerror: Option<String>,
+
+    /// Command run after a failed capture start or an exit of the running exec with --restart-on-exit ({R} replaced with the rate, {ERR} with the error or exit status)
+    #[clap(long, validator = cmdline::check)]
+    c_onerror: Option<String>,
+
+    /// Long-running playback command while stopped at rate 0 after running, killed by the next start ({R} and {PREV_R} replaced with the stopped rate)
+    #[clap(long, validator = cmdline::check)]
+    p_idlecmd: Option<String>,
+
+    /// Command run on a rate change of the running playback exec instead of restarting it ({R} replaced with the new rate, {PREV_R} with the previous one)
+    #[clap(long, validator = cmdline::check)]
+    p_reload_cmd: Option<String>,
+
+    /// Command run on a rate change of the running capture exec instead of restarting it ({R} replaced with the new rate, {PREV_R} with the previous one)
+    #[clap(long, validator = cmdline::check)]
+    c_reload_cmd: Option<String>,
+
+    /// Long-running capture command while stopped at rate 0 after running, killed by the next start ({R} and {PREV_R} replaced with the stopped rate)
+    #[clap(long, validator = cmdline::check)]
+    c_idlecmd: Option<String>,
+
+    /// File with the playback command, lines joined, # comments and blank lines ignored. Overrides pcmd
+    #[clap(long)]
+    pcmd_file: Option<String>,
+
+    /// File with the capture command, lines joined, # comments and blank lines ignored. Overrides ccmd
+    #[clap(long)]
+    ccmd_file: Option<String>,
+
+    /// Copy PCM in-process instead of running pcmd/ccmd (e.g. alsaloop)
+    #[clap(long)]
+    builtin_loop: bool,
+
+    /// Builtin loop playback source device
+    #[clap(long, default_value = "hw:Loopback,1")]
+    ploop_from: String,
+
+    /// Builtin loop playback target device
+    #[clap(long, default_value = "hw:UAC2Gadget")]
+    ploop_to: String,
+
+    /// Builtin loop capture source device
+    #[clap(long, default_value = "hw:UAC2Gadget")]
+    cloop_from: String,
+
+    /// Builtin loop capture target device
+    #[clap(long, default_value = "hw:Loopback,1")]
+    cloop_to: String,
+
+    /// Builtin loop sample format
+    #[clap(long, arg_enum, default_value = "s32-le")]
+    loop_format: LoopFormat,
+
+    /// Builtin loop channels
+    #[clap(long, default_value_t = 2)]
+    loop_channels: u32,
+
+    /// Builtin loop period size in frames
+    #[clap(long, default_value_t = 1024)]
+    loop_period_frames: usize,
+
+    /// CamillaDSP websocket HOST:PORT switched to --pcamilla-config on playback rate changes instead of running pcmd
+    #[clap(long, value_name = "HOST:PORT", requires = "pcamilla-config", conflicts_with = "builtin-loop")]
+    pcamilla: Option<String>,
+
+    /// CamillaDSP websocket HOST:PORT switched to --ccamilla-config on capture rate changes instead of running ccmd
+    #[clap(long, value_name = "HOST:PORT", requires = "ccamilla-config", conflicts_with = "builtin-loop")]
+    ccamilla: Option<String>,
+
+    /// CamillaDSP config path for the playback rate ({R} replaced with real rate)
+    #[clap(long)]
+    pcamilla_config: Option<String>,
+
+    /// CamillaDSP config path for the capture rate ({R} replaced with real rate)
+    #[clap(long)]
+    ccamilla_config: Option<String>,
+
+    /// Send the CamillaDSP config contents ({R} replaced) by SetConfig instead of the path by SetConfigName
+    #[clap(long)]
+    camilla_inline: bool,
+
+    /// Command run on TLV change of a rate ctl ({DIR} replaced with the direction, {TLV} with the TLV words in hex)
+    #[clap(long, validator = cmdline::check)]
+    tlv_cmd: Option<String>,
+
+    /// Placeholder in pcmd/ccmd replaced with real rate
+    #[clap(long, default_value = "{R}", parse(try_from_str = parse_rate_token))]
+    rate_token: String,
+
+    /// Multiplier N or NUM/DEN of the rate passed to pcmd, the scaled rate must be an integer
+    #[clap(long, default_value_t = RateScale::default())]
+    prate_scale: RateScale,
+
+    /// Multiplier N or NUM/DEN of the rate passed to ccmd, the scaled rate must be an integer
+    #[clap(long, default_value_t = RateScale::default())]
+    crate_scale: RateScale,
+
+    /// Pass the rate to pcmd/ccmd also in environment variable NAME
+    #[clap(long, value_name = "NAME")]
+    rate_env: Option<String>,
+
+    /// Playback/capture command stdout, log with --json-events
+    #[clap(long, arg_enum, default_value = "inherit", default_value_if("json-events", None, Some("log")))]
+    child_stdout: ChildOutput,
+
+    /// Playback/capture command stderr
+    #[clap(long, arg_enum, default_value = "inherit")]
+    child_stderr: ChildOutput,
+
+    /// Log level of the playback/capture command output lines logged by --child-stdout/--child-stderr log
+    #[clap(long, default_value = "info")]
+    child_log_level: Level,
+
+    /// Rates differing from the running rate by up to N Hz are treated as unchanged, not restarting the exec
+    #[clap(long, default_value_t = 0)]
+    rate_tolerance: usize,
+
+    /// Restart the running execs at the current rate when their executable changes, e.g. upgraded
+    #[clap(long)]
+    watch_binary: bool,
+
+    /// Restart the exec at the current rate when it exits on its own, e.g. crashed
+    #[clap(long)]
+    restart_on_exit: bool,
+
+    /// Max consecutive restarts of an exited exec, then given up until the next rate change, 0 = unlimited
+    #[clap(long, default_value_t = 0)]
+    max_restarts: usize,
+
+    /// Signal stopping the execs, e.g. SIGTERM, INT, 9
+    #[clap(long, parse(try_from_str = parse_signal), default_value = "SIGTERM")]
+    stop_signal: Signal,
+
+    /// Time in ms for the execs to finish after --stop-signal, then killed with SIGKILL, 0 = SIGKILL right away
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 2000)]
+    kill_timeout_ms: usize,
+
+    /// Signal sent to the running execs on a rate change instead of restarting them, e.g. SIGHUP
+    #[clap(long, parse(try_from_str = parse_signal))]
+    reload_signal: Option<Signal>,
+
+    /// Restart the exec when the same nonzero rate is read again, instead of ignoring it
+    #[clap(long)]
+    restart_same_rate: bool,
+
+    /// Prefix log lines with syslog priority for journald, logged exec stderr with warning priority
+    #[clap(long)]
+    journald: bool,
+
+    /// Keep execs running on quit and adopt them at next start if unchanged, state files stored in DIR
+    #[clap(long, value_name = "DIR")]
+    takeover: Option<String>,
+
+    /// Run execs only while the file exists, checked at start and periodically while running
+    #[clap(long, value_name = "PATH")]
+    gate_file: Option<String>,
+
+    /// Switch to the user after opening the card, the commands run as the user too
+    #[clap(long)]
+    user: Option<String>,
+
+    /// Switch to the group after opening the card, defaults to the primary group of --user
+    #[clap(long)]
+    group: Option<String>,
+
+    /// Write the current playback rate to the file on each change, 0 on stop
+    #[clap(long, value_name = "PATH")]
+    prate_file: Option<String>,
+
+    /// Write the current capture rate to the file on each change, 0 on stop
+    #[clap(long, value_name = "PATH")]
+    crate_file: Option<String>,
+
+    /// Write the PIDs of the running playback execs to the file, removed when stopped
+    #[clap(long, value_name = "PATH")]
+    ppid_file: Option<String>,
+
+    /// Write the PIDs of the running capture execs to the file, removed when stopped
+    #[clap(long, value_name = "PATH")]
+    cpid_file: Option<String>,
+
+    /// Start with capture disabled (enabled via control socket)
+    #[clap(long)]
+    disable_capture: bool,
+
+    /// Start with playback disabled (enabled via control socket)
+    #[clap(long)]
+    disable_playback: bool,
+
+    /// TOML config file with the params, keys as printed by --print-config, params given on the command line take precedence
+    #[clap(long, value_name = "PATH")]
+    config: Option<String>,
+
+    /// Print the effective configuration including defaults as TOML and exit
+    #[clap(long)]
+    print_config: bool,
+
+    /// Print the commands resolved for the current rates and exit
+    #[clap(long)]
+    print_resolved: bool,
+
+    /// Refuse to start on misconfiguration instead of warning
+    #[clap(long)]
+    strict: bool,
+
+    /// Run the commands and hooks via sh -c, allowing pipes, redirections and variable expansion. The literal args are run directly
+    #[clap(long)]
+    shell: bool,
+
+    /// Only log the commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Append every ctl event to the file, for --replay
+    #[clap(long)]
+    trace_events: Option<String>,
+
+    /// Replay events from a --trace-events file offline, implies --dry-run
+    #[clap(long)]
+    replay: Option<String>,
+
+    /// Soak test without the card: repeatedly feed the comma-separated rates to both directions, e.g. 48000,0,44100
+    #[clap(long, use_value_delimiter = true, value_name = "RATES")]
+    soak: Vec<usize>,
+
+    /// Interval of the soak rate changes in ms
+    #[clap(long, parse(try_from_str = parse_duration_ms), default_value_t = 100)]
+    soak_interval_ms: usize,
+
+    /// Number of passes over the soak rates
+    #[clap(long, default_value_t = 10)]
+    soak_cycles: usize,
+
+    /// Control socket path (status queries, forced start/stop, quit), e.g. /run/gaudio_ctl.sock
+    #[clap(short = 's', long)]
+    socket: Option<String>,
 
-    /// Capture command ({R} replaced with real rate)
-    #[clap(short = 'y', long, default_value = "alsaloop -vv -r {R} --latency=1000 -f S32_LE -S captshift -C hw:UAC2Gadget -P hw:Loopback,1")]
-    ccmd: String,
+    /// Serve Prometheus metrics on http://ADDR/metrics, e.g. 0.0.0.0:9464
+    #[clap(long, value_name = "ADDR")]
+    metrics: Option<String>,
+
+    /// Register on D-Bus (session or system bus)
+    #[cfg(feature = "dbus")]
+    #[clap(long, possible_values = ["session", "system"])]
+    dbus: Option<String>,
+
+    /// Desktop notifications on starts/stops of the execs, requires a build with feature dbus
+    #[clap(long)]
+    notify: bool,
 }
 
-// messages sent to exec threads
-pub enum Msg {
-    // stop exec
-    StopExec,
-    // start with rate
-    StartExec(usize),
-    // stop the thread
-    Quit,
+#[derive(clap::Subcommand, Debug)]
+enum Cmd {
+    /// Print all ctl elements of the card (--gadget-name) with their values, rate-like ones marked with '*'
+    ListControls,
 }
 
 struct ExecLocData {
     dir: String,
-    canceller: Canceller,
-    debouncing_now: Arc<AtomicBool>,
-    sender: Sender<Msg>,
-    draining_recv: Receiver<Msg>,
+    executor: Executor,
     last_start: Option<Instant>,
+    // last rate read in the poll mode
+    polled_rate: usize,
+    enabled: Arc<AtomicBool>,
+    last_rate: Arc<AtomicUsize>,
+    // last rate sent to the exec thread, None = unknown
+    dispatched_rate: Option<usize>,
+    // repeated nonzero rate restarts the exec
+    restart_same_rate: bool,
+    // copy of the exec thread commands for resolving
+    cmds: Arc<Mutex<Vec<CmdCfg>>>,
+    simulated: Arc<AtomicBool>,
+    // file with the dispatched rate for non-alsa consumers
+    rate_file: Option<String>,
 }
 
 impl ExecLocData {
-    pub fn new(dir: &str, canceller: Canceller, debouncing_now: Arc<AtomicBool>, sender: Sender<Msg>, recv: Receiver<Msg>) -> Self {
+    pub fn new(dir_cfg: &DirCfg, executor: Executor, cmds: Arc<Mutex<Vec<CmdCfg>>>) -> Self {
         ExecLocData {
-            dir: dir.to_string(),
-            canceller,
-            debouncing_now,
-            sender,
-            draining_recv: recv,
+            dir: dir_cfg.dir.clone(),
+            executor,
             last_start: None,
+            polled_rate: 0,
+            enabled: Arc::new(AtomicBool::new(dir_cfg.enabled)),
+            last_rate: Arc::new(AtomicUsize::new(0)),
+            dispatched_rate: None,
+            restart_same_rate: dir_cfg.restart_same_rate,
+            cmds,
+            simulated: Arc::new(AtomicBool::new(false)),
+            rate_file: dir_cfg.rate_file.clone(),
+        }
+    }
+
+    // stopping at once without the debouncing and the stop grace period, e.g. after the card removal
+    fn stop_now(&mut self) -> Result<()> {
+        self.last_rate.store(0, Ordering::SeqCst);
+        self.polled_rate = 0;
+        if self.dispatched_rate.replace(0) == Some(0) {
+            return Ok(());
+        }
+        if let Some(path) = &self.rate_file {
+            if let Err(err) = write_rate_file(path, 0) {
+                warn!("{}: Cannot write rate file {}, error: {}", self.dir, path, err);
+            }
+        }
+        self.executor.send(Msg::StopExec)?;
+        self.executor.send(Msg::Flush)
+    }
+
+    pub fn handle(&self) -> DirHandle {
+        DirHandle {
+            dir: self.dir.clone(),
+            state: self.executor.state().clone(),
+            sender: self.executor.sender().clone(),
+            enabled: self.enabled.clone(),
+            last_rate: self.last_rate.clone(),
+            cmds: self.cmds.clone(),
+            simulated: self.simulated.clone(),
         }
     }
 }
 
-struct CtlData<'a> {
-    elem: Elem<'a>,
-    numid: u32,
+// per-direction params of the executor
+pub struct DirCfg {
+    dir: String,
+    // argv of each command
+    cmds: Vec<Vec<String>>,
+    // argv of each command overriding cmds for the rate ranges
+    rate_cmds: Vec<(RateRanges, Vec<String>)>,
+    onstop: Option<String>,
+    onerror: Option<String>,
+    idlecmd: Option<String>,
+    reloadcmd: Option<String>,
+    tlvcmd: Option<String>,
+    // hooks and rate cmds run via sh -c
+    shell: bool,
+    // cmds run via sh -c, not the literal args
+    shell_cmds: bool,
+    enabled: bool,
+    restart_same_rate: bool,
+    // replacing the commands
+    builtin: Option<LoopCfg>,
+    camilla: Option<CamillaCfg>,
+    rate_scale: RateScale,
+    rate_file: Option<String>,
+    pid_file: Option<String>,
+    // (device, subdevice) of the rate ctl
+    coords: (u32, u32),
+}
+
+fn capture_cfg(args: &Args) -> Result<DirCfg> {
+    Ok(DirCfg {
+        dir: "Capture".to_string(),
+        cmds: get_cmds(&args.ccmd_file, &args.ccmd, &args.carg, args.shell)?,
+        rate_cmds: split_rate_cmds(&args.crate_cmd, args.shell)?,
+        onstop: args.c_onstop.clone(),
+        onerror: args.c_onerror.clone(),
+        idlecmd: args.c_idlecmd.clone(),
+        reloadcmd: args.c_reload_cmd.clone(),
+        tlvcmd: args.tlv_cmd.clone(),
+        shell: args.shell,
+        shell_cmds: args.shell && (args.ccmd_file.is_some() || args.carg.is_empty()),
+        enabled: !args.disable_capture,
+        restart_same_rate: args.restart_same_rate,
+        builtin: loop_cfg(args, &args.cloop_from, &args.cloop_to),
+        camilla: camilla_cfg(args, &args.ccamilla, &args.ccamilla_config, args.crate_scale),
+        rate_scale: args.crate_scale,
+        rate_file: args.crate_file.clone(),
+        pid_file: args.cpid_file.clone(),
+        coords: (args.cdevice, args.csubdevice),
+    })
+}
+
+fn playback_cfg(args: &Args) -> Result<DirCfg> {
+    Ok(DirCfg {
+        dir: "Playback".to_string(),
+        cmds: get_cmds(&args.pcmd_file, &args.pcmd, &args.parg, args.shell)?,
+        rate_cmds: split_rate_cmds(&args.prate_cmd, args.shell)?,
+        onstop: args.p_onstop.clone(),
+        onerror: args.p_onerror.clone(),
+        idlecmd: args.p_idlecmd.clone(),
+        reloadcmd: args.p_reload_cmd.clone(),
+        tlvcmd: args.tlv_cmd.clone(),
+        shell: args.shell,
+        shell_cmds: args.shell && (args.pcmd_file.is_some() || args.parg.is_empty()),
+        enabled: !args.disable_playback,
+        restart_same_rate: args.restart_same_rate,
+        builtin: loop_cfg(args, &args.ploop_from, &args.ploop_to),
+        camilla: camilla_cfg(args, &args.pcamilla, &args.pcamilla_config, args.prate_scale),
+        rate_scale: args.prate_scale,
+        rate_file: args.prate_file.clone(),
+        pid_file: args.ppid_file.clone(),
+        coords: (args.pdevice, args.psubdevice),
+    })
+}
+
+fn watch_cfg(args: &Args, spec: &WatchSpec, index: usize) -> Result<DirCfg> {
+    Ok(DirCfg {
+        dir: spec.label(index),
+        cmds: vec![split_cmd(&spec.cmd, args.shell).with_context(|| format!("Invalid command of watch '{}'", spec.name))?],
+        rate_cmds: Vec::new(),
+        onstop: None,
+        onerror: None,
+        idlecmd: None,
+        reloadcmd: None,
+        tlvcmd: args.tlv_cmd.clone(),
+        shell: args.shell,
+        shell_cmds: args.shell,
+        enabled: true,
+        restart_same_rate: args.restart_same_rate,
+        builtin: None,
+        camilla: None,
+        rate_scale: RateScale::default(),
+        rate_file: None,
+        pid_file: None,
+        coords: (spec.device, spec.subdevice),
+    })
+}
+
+fn loop_cfg(args: &Args, from: &str, to: &str) -> Option<LoopCfg> {
+    args.builtin_loop.then(|| LoopCfg {
+        capture_dev: from.to_string(),
+        playback_dev: to.to_string(),
+        format: args.loop_format,
+        channels: args.loop_channels,
+        period_frames: args.loop_period_frames,
+        dry_run: args.dry_run || args.replay.is_some(),
+    })
+}
+
+fn camilla_cfg(args: &Args, addr: &Option<String>, config: &Option<String>, rate_scale: RateScale) -> Option<CamillaCfg> {
+    addr.as_ref().zip(config.as_ref()).map(|(addr, config)| CamillaCfg {
+        addr: addr.clone(),
+        config: config.clone(),
+        inline: args.camilla_inline,
+        rate_token: args.rate_token.clone(),
+        rate_scale,
+        dry_run: args.dry_run || args.replay.is_some(),
+    })
+}
+
+// extra ctl of --watch with its executor
+struct Watched<'a> {
+    ctl_data: CtlData<'a>,
+    exec_data: ExecLocData,
+    // held until exit
+    _lock: Option<ctl_lock::CtlLock>,
 }
 
 fn main() -> Result<()> {
-    let args: Args = Args::parse();
+    let cmd = Args::command();
+    let matches = match with_config(&cmd, cmd.clone().get_matches()) {
+        Ok(matches) => matches,
+        Err(err) => match err.downcast::<clap::Error>() {
+            Ok(err) => err.exit(),
+            Err(err) => return Err(err),
+        },
+    };
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if args.print_config {
+        print_config(&cmd, &matches);
+        return Ok(());
+    }
     init_logging(&args);
+    // the explicit inherit only, the default is log then
+    if args.json_events && args.child_stdout == ChildOutput::Inherit {
+        return Err(anyhow!("--json-events cannot be used with --child-stdout inherit, the process output would mix with the events"));
+    }
+    // not by clap, the printed default must load from the config file
+    if args.max_restarts > 0 && !args.restart_on_exit {
+        return Err(anyhow!("--max-restarts requires --restart-on-exit"));
+    }
+    // accepted also without the feature, rather than an unknown param
+    #[cfg(not(feature = "dbus"))]
+    if args.notify {
+        return Err(anyhow!("--notify requires gaudio_ctl built with feature dbus (cargo build --features dbus)"));
+    }
+    watch::check_labels(&args.watch)?;
+    if let Some(Cmd::ListControls) = args.command {
+        return ctl_list::print_controls(&card_devname(&args, false)?);
+    }
     debug!("{:#?}", args);
+    signals::start_signal_thread()?;
 
-    let devname = format!("hw:{}", args.gadget_name).to_string();
+    let exec_cfg = build_exec_cfg(&args);
+    check_watchdog(args.watchdog_ms, &exec_cfg)?;
+    let mut spawn_cfg = build_spawn_cfg(&args);
+
+    if let Some(path) = &args.replay {
+        return replay(path, &args, &spawn_cfg, exec_cfg);
+    }
+    if !args.soak.is_empty() {
+        return soak(&args, &spawn_cfg, exec_cfg);
+    }
 
     // initializing rate ctrls and corresponding executors
-    let h = HCtl::new(&devname, false)?;
-    h.load()?;
+    // the error lists the available cards
+    card_devname(&args, args.wait_forever)?;
+    let (mut devname, mut h) = match open_hctl(&args, args.wait_forever)? {
+        Some(opened) => opened,
+        None => return Ok(()),
+    };
+    spawn_cfg.card = card_id(&devname)?;
 
-    let c_ctl_data = get_ctl_data(&h, args.cctl.as_str())?;
-    let mut c_exec_data = match c_ctl_data {
+    // mirroring direction runs without its own ctl
+    let c_mirrored = args.mirror == Some(Direction::Playback);
+    let mut c_ctl_data = match c_mirrored {
+        true => None,
+        false => resolve_ctl(&h, &devname, &args, Direction::Capture)?,
+    };
+    // held until exit
+    let mut c_lock = lock_ctl(&args, &devname, &c_ctl_data)?;
+    let c_cfg = capture_cfg(&args)?;
+    let c_unusable = (c_ctl_data.is_some() || c_mirrored) && !usable_cmds(&c_cfg, &spawn_cfg, args.strict)?;
+    if c_unusable {
+        c_ctl_data = None;
+    }
+    let c_exec_data = match c_ctl_data {
         Some(_) => {
             trace!("Ctl '{}' found, will start capture exec", args.cctl);
-            Some(init_executor("Capture", args.ccmd, args.timeout)?)
+            Some(init_executor(c_cfg, &spawn_cfg, exec_cfg.clone())?)
+        }
+        None if c_unusable => None,
+        None if c_mirrored => {
+            info!("Capture mirrors playback rate, ctl '{}' ignored", args.cctl);
+            Some(init_executor(c_cfg, &spawn_cfg, exec_cfg.clone())?)
         }
         None => {
             info!("Ctl '{}' not found, will not start capture exec", args.cctl);
@@ -116,11 +797,35 @@ fn main() -> Result<()> {
         }
     };
 
-    let p_ctl_data = get_ctl_data(&h, args.pctl.as_str())?;
-    let mut p_exec_data = match p_ctl_data {
+    let p_mirrored = args.mirror == Some(Direction::Capture);
+    let mut p_ctl_data = match p_mirrored {
+        true => None,
+        false => resolve_ctl(&h, &devname, &args, Direction::Playback)?,
+    };
+    let numid_conflict = matches!((&c_ctl_data, &p_ctl_data), (Some(c), Some(p)) if c.numid == p.numid);
+    if numid_conflict {
+        let msg = format!("Ctls '{}' and '{}' resolve to the same num ID", args.cctl, args.pctl);
+        if args.strict {
+            return Err(anyhow!("{}, exiting", msg));
+        }
+        warn!("{}, only capture will be handled", msg);
+        p_ctl_data = None;
+    }
+    let mut p_lock = lock_ctl(&args, &devname, &p_ctl_data)?;
+    let p_cfg = playback_cfg(&args)?;
+    let p_unusable = (p_ctl_data.is_some() || p_mirrored) && !usable_cmds(&p_cfg, &spawn_cfg, args.strict)?;
+    if p_unusable {
+        p_ctl_data = None;
+    }
+    let p_exec_data = match p_ctl_data {
         Some(_) => {
             trace!("Ctl '{}' found, will start playback exec", args.pctl);
-            Some(init_executor("Playback", args.pcmd, args.timeout)?)
+            Some(init_executor(p_cfg, &spawn_cfg, exec_cfg.clone())?)
+        }
+        None if numid_conflict || p_unusable => None,
+        None if p_mirrored => {
+            info!("Playback mirrors capture rate, ctl '{}' ignored", args.pctl);
+            Some(init_executor(p_cfg, &spawn_cfg, exec_cfg.clone())?)
         }
         None => {
             info!("Ctl '{}' not found, will not start playback exec", args.pctl);
@@ -128,111 +833,1315 @@ fn main() -> Result<()> {
         }
     };
 
-    if c_ctl_data.is_none() && p_ctl_data.is_none() {
-        return Err(anyhow!("Neither capture nor playback rate controls found, exiting"));
+    let taken: Vec<u32> = [&c_ctl_data, &p_ctl_data].iter()
+        .filter_map(|ctl_data| ctl_data.as_ref())
+        .flat_map(numids)
+        .collect();
+    let watched = init_watches(&h, &devname, &args, &spawn_cfg, &exec_cfg, taken)?;
+
+    log_resolution("Capture", &c_ctl_data, &c_exec_data, c_mirrored);
+    log_resolution("Playback", &p_ctl_data, &p_exec_data, p_mirrored);
+
+    if c_ctl_data.is_none() && p_ctl_data.is_none() && watched.is_empty() {
+        if c_unusable || p_unusable {
+            return Err(anyhow!("No direction with usable commands, exiting"));
+        }
+        return Err(Error::CtlNotFound { cctl: args.cctl.clone(), pctl: args.pctl.clone() }.into());
+    }
+
+    let mut reg = Registry { c_ctl_data, c_exec_data, p_ctl_data, p_exec_data, watched };
+    if args.print_resolved {
+        return print_resolved(&reg);
     }
 
-    // subscribing for blocking ctl.read
-    let ctl = Ctl::new(&devname, false)?;
-    ctl.subscribe_events(true)?;
+    let dirs: Vec<DirHandle> = reg.exec_datas().map(|d| d.handle()).collect();
+    // removed on exit
+    let _socket = match &args.socket {
+        Some(path) => Some(socket::start_socket_thread(path, dirs.clone())?),
+        None => None,
+    };
+    if let Some(addr) = &args.metrics {
+        metrics::start_metrics_thread(addr, dirs.clone())?;
+    }
+    if args.watchdog_ms > 0 {
+        watchdog::start_watchdog_thread(dirs.clone(), args.watchdog_ms, args.watchdog_abort)?;
+    }
+    #[cfg(feature = "dbus")]
+    if let Some(bus) = &args.dbus {
+        dbus::start_dbus_thread(bus, dirs.clone())?;
+    }
+    #[cfg(feature = "dbus")]
+    if args.notify {
+        if let Err(err) = dbus::start_notify_thread(dirs.clone()) {
+            warn!("Desktop notifications unavailable, error: {}", err);
+        }
+    }
+
+    if args.startup_delay_ms > 0 {
+        info!("Startup delay - waiting {} ms for the card to settle", args.startup_delay_ms);
+        if signals::shutdown_within(Duration::from_millis(args.startup_delay_ms as u64))? {
+            return reg.shutdown();
+        }
+    }
+    // after the card re-appeared the current rates are sent by the attach
+    let mut initial = !args.no_initial_rates;
+    let result = loop {
+        let result = match args.poll_interval_ms > 0 {
+            true => poll_loop(&h, &devname, &args, &mut reg, initial),
+            false => event_loop(&h, &devname, &args, &mut reg, initial),
+        };
+        initial = false;
+        let err = match result {
+            Err(err) if card_gone(&err, &devname) => err,
+            result => break result,
+        };
+        warn!("Card {} removed, stopping execs until it re-appears, error: {:#}", devname, err);
+        let mut detached = reg.detach();
+        detached.stop()?;
+        (devname, h) = match open_hctl(&args, true)? {
+            Some(opened) => opened,
+            None => return detached.shutdown(),
+        };
+        info!("Card {} re-appeared, resuming", devname);
+        // the card index may have changed, the locks are released before locking the new names
+        drop((c_lock.take(), p_lock.take()));
+        reg = detached.attach(&h, &devname, &args)?;
+        c_lock = lock_ctl(&args, &devname, &reg.c_ctl_data)?;
+        p_lock = lock_ctl(&args, &devname, &reg.p_ctl_data)?;
+    };
+    // also after a failed loop, no exec left running
+    let shutdown = reg.shutdown();
+    drop((c_lock, p_lock));
+    result.and(shutdown)
+}
+
+// the command line params after the config ones, overriding them
+fn with_config(cmd: &ClapCommand, matches: ArgMatches) -> Result<ArgMatches> {
+    let path = match matches.value_of("config") {
+        Some(path) => path,
+        None => return Ok(matches),
+    };
+    // command line values replace the config ones, also of the repeated params
+    let given: Vec<&str> = cmd.get_arguments()
+        .map(|arg| arg.get_id())
+        .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .collect();
+    let mut argv: Vec<OsString> = env::args_os().take(1).collect();
+    argv.extend(config::config_args(path, cmd, &given)?.into_iter().map(OsString::from));
+    argv.extend(env::args_os().skip(1));
+    Ok(cmd.clone().args_override_self(true).try_get_matches_from(argv)?)
+}
+
+// the watchdog must not report the intended waits of a single rate change
+fn check_watchdog(watchdog_ms: usize, exec_cfg: &ExecCfg) -> Result<()> {
+    if watchdog_ms == 0 {
+        return Ok(());
+    }
+    match exec_cfg.busy_window() {
+        None => Err(anyhow!("--watchdog-ms cannot be used with unlimited --hook-timeout-ms")),
+        Some(window) if watchdog_ms <= window => Err(anyhow!(
+            "--watchdog-ms {} must exceed {} ms, the longest handling of a rate change \
+             (debouncing, stop grace/keepalive, 3x hook timeout, 2x kill timeout, confirm)", watchdog_ms, window)),
+        Some(_) => Ok(()),
+    }
+}
+
+fn build_exec_cfg(args: &Args) -> ExecCfg {
+    ExecCfg {
+        timeout: args.timeout,
+        stop_grace: args.stop_grace_ms,
+        keepalive: args.keepalive_ms,
+        show_timing: args.show_timing,
+        max_runtime: args.max_runtime_ms,
+        hook_timeout: args.hook_timeout_ms,
+        takeover_dir: args.takeover.clone(),
+        gate_file: args.gate_file.clone(),
+        confirm: args.confirm_ms,
+        debounce_mode: args.debounce_mode,
+        stable: args.stable_ms,
+        stable_max: args.stable_max_ms,
+        rate_tolerance: args.rate_tolerance,
+        watch_binary: args.watch_binary,
+        restart_on_exit: args.restart_on_exit,
+        max_restarts: args.max_restarts,
+        backoff_base: args.backoff_base_ms,
+        backoff_max: args.backoff_max_ms,
+        pid_file: None,
+        stop_signal: args.stop_signal,
+        kill_timeout: args.kill_timeout_ms,
+        reload_signal: args.reload_signal,
+        camilla: None,
+        json_events: args.json_events,
+    }
+}
+
+fn build_spawn_cfg(args: &Args) -> SpawnCfg {
+    SpawnCfg {
+        rate_token: args.rate_token.clone(),
+        rate_env: args.rate_env.clone(),
+        rate_aliases: args.rate_alias.iter().cloned().collect(),
+        stdout: args.child_stdout,
+        stderr: args.child_stderr,
+        log_level: args.child_log_level,
+        dry_run: args.dry_run || args.replay.is_some(),
+        journald: args.journald,
+        rate_scale: RateScale::default(),
+        // replaced with the card ID once the card is open
+        card: args.gadget_name.clone(),
+        device: 0,
+        subdevice: 0,
+    }
+}
+
+// initial = acting on the rates already set
+fn event_loop<'a>(h: &'a HCtl, devname: &str, args: &Args, reg: &mut Registry<'a>, initial: bool) -> Result<()> {
+    let watcher = Watcher::open(devname)?;
+    // the already opened ctls stay accessible
+    drop_privileges(args)?;
+    // read after subscribing, no change missed, the execs spawned unprivileged
+    if initial {
+        reg.send_current_rates(args)?;
+    }
+    let mut tracer = args.trace_events.as_deref().map(EventTracer::new).transpose()?;
+    let mut warmup = args.warmup_events;
+    if warmup > 0 {
+        info!("Discarding first {} ctl events as warm-up", warmup);
+    }
+    // coupling only with both directions handled on their own
+    let mut coupling = match (args.couple_ms, reg.c_exec_data.is_some() && reg.p_exec_data.is_some()) {
+        (couple_ms, true) if couple_ms > 0 && args.mirror.is_none() => Some(Coupling::new(couple_ms)),
+        _ => None,
+    };
+    let event_idle = Duration::from_millis(args.event_idle_ms as u64);
+    let mut last_event = Instant::now();
     loop {
-        let event = ctl.read()?.unwrap();
+        // the idle check only while an exec runs
+        let any_running = running(&reg.c_exec_data) || running(&reg.p_exec_data)
+            || reg.watched.iter().any(|w| matches!(w.exec_data.executor.state().get(), ExecState::Running(_)));
+        let idle_left = match args.event_idle_ms > 0 && any_running {
+            true => Some(event_idle.saturating_sub(last_event.elapsed())),
+            false => None,
+        };
+        let timeout = [coupling.as_ref().and_then(Coupling::remaining), idle_left].into_iter().flatten().min();
+        match watcher.wait(timeout)? {
+            Ready::Wake(Wake::Shutdown) => {
+                info!("Event loop finished");
+                return Ok(());
+            }
+            Ready::Wake(Wake::Reload) => {
+                if let Err(err) = reload(h, devname, args, reg) {
+                    warn!("Reload failed, keeping the current config, error: {:#}", err);
+                }
+                continue;
+            }
+            Ready::Timeout => {
+                if let Some((dir, rate)) = coupling.as_mut().and_then(Coupling::expired) {
+                    let exec_data = reg.exec_of(dir).unwrap();
+                    debug!("{}: Other direction unchanged within {}ms, starting alone", exec_data.dir, args.couple_ms);
+                    send_rate(rate, exec_data, args.show_timing)?;
+                }
+                if idle_left.is_some() && last_event.elapsed() >= event_idle {
+                    reconcile(devname, args, reg)?;
+                    last_event = Instant::now();
+                }
+                continue;
+            }
+            Ready::Ctl => last_event = Instant::now(),
+        }
+        let event = match watcher.read()? {
+            Some(event) => event,
+            None => continue,
+        };
+        if warmup > 0 {
+            warmup -= 1;
+            debug!("Discarded warm-up event: elem num ID {}, {} remaining", event.numid, warmup);
+            continue;
+        }
         // determining event control
-        let numid = event.get_id().get_numid();
-        trace!("Received event: elem num ID {}, index {}, mask {}", numid, event.get_id().get_index(), event.get_mask().0);
-        if fits_numid(&c_ctl_data, numid) {
+        let CtlEvent { numid, index, mask, .. } = event;
+        trace!("Received event: elem num ID {}, index {}, mask {}", numid, index, mask);
+        if event.tlv {
+            let exec_data = if fits_numid(&reg.c_ctl_data, numid, index) {
+                reg.c_exec_data.as_ref()
+            } else if fits_numid(&reg.p_ctl_data, numid, index) {
+                reg.p_exec_data.as_ref()
+            } else {
+                reg.watched.iter().find(|w| fits_ctl(&w.ctl_data, numid, index)).map(|w| &w.exec_data)
+            };
+            if let Some(exec_data) = exec_data {
+                handle_tlv_change(devname, exec_data, numid, args.tlv_cmd.is_some())?;
+            }
+        }
+        if !event.value {
+            // info/TLV/add/remove changes do not carry a new rate
+            trace!("Ignoring event mask {} without value change", mask);
+            if let Some(tracer) = tracer.as_mut() {
+                tracer.record(numid, index, mask, None, None)?;
+            }
+            continue;
+        }
+        let target = if fits_numid(&reg.c_ctl_data, numid, index) {
             // capture rate
-            send_new_rate(&c_ctl_data.as_ref().unwrap().elem, c_exec_data.as_mut().unwrap(), args.show_timing)?;
-        } else if fits_numid(&p_ctl_data, numid) {
+            Some((reg.c_ctl_data.as_ref().unwrap(), Direction::Capture))
+        } else if fits_numid(&reg.p_ctl_data, numid, index) {
             // playback rate
-            send_new_rate(&p_ctl_data.as_ref().unwrap().elem, p_exec_data.as_mut().unwrap(), args.show_timing)?;
+            Some((reg.p_ctl_data.as_ref().unwrap(), Direction::Playback))
+        } else {
+            None
+        };
+        let dispatched = match target {
+            Some((ctl_data, dir)) => {
+                let rate = read_rate(ctl_data)?;
+                let exec_data = reg.exec_of(dir).unwrap();
+                if let Some(tracer) = tracer.as_mut() {
+                    tracer.record(numid, index, mask, Some(&exec_data.dir), Some(rate))?;
+                }
+                let sends = match coupling.as_mut() {
+                    Some(coupling) => coupling.offer(dir, rate),
+                    None => vec![(dir, rate)],
+                };
+                for (dir, rate) in sends {
+                    send_rate(rate, reg.exec_of(dir).unwrap(), args.show_timing)?;
+                }
+                Some(rate)
+            }
+            None => {
+                let watch = reg.watched.iter_mut().find(|w| fits_ctl(&w.ctl_data, numid, index));
+                let rate = watch.as_ref().map(|w| read_rate(&w.ctl_data)).transpose()?;
+                if let Some(tracer) = tracer.as_mut() {
+                    tracer.record(numid, index, mask, watch.as_ref().map(|w| w.exec_data.dir.as_str()), rate)?;
+                }
+                if let (Some(watch), Some(rate)) = (watch, rate) {
+                    send_rate(rate, &mut watch.exec_data, args.show_timing)?;
+                }
+                // not mirrored
+                None
+            }
+        };
+        if let (Some(rate), Some(slave)) = (dispatched, reg.mirror_slave(args.mirror)) {
+            send_rate(rate, slave, args.show_timing)?;
+        }
+    }
+}
+
+fn running(exec_data: &Option<ExecLocData>) -> bool {
+    matches!(exec_data.as_ref().map(|data| data.executor.state().get()), Some(ExecState::Running(_)))
+}
+
+// safety net for missed ctl events, dispatching rates differing from the last reported ones
+fn reconcile(devname: &str, args: &Args, reg: &mut Registry) -> Result<()> {
+    if signals::paused() {
+        return Ok(());
+    }
+    trace!("No ctl event within {}ms, re-reading rate ctls", args.event_idle_ms);
+    for dir in [Direction::Capture, Direction::Playback] {
+        let handled = reg.exec_of(dir).is_some();
+        let rate = match reg.ctl_of(dir) {
+            Some(ctl_data) if handled => reconciled_rate(&ctl_data.name, read_rate(ctl_data), devname)?,
+            _ => continue,
+        };
+        let rate = match rate {
+            Some(rate) => rate,
+            None => continue,
+        };
+        let exec_data = reg.exec_of(dir).unwrap();
+        if rate == exec_data.last_rate.load(Ordering::SeqCst) {
+            continue;
+        }
+        warn!("{}: Rate {} read without ctl event within {}ms, reconciling", exec_data.dir, rate, args.event_idle_ms);
+        send_rate(rate, exec_data, args.show_timing)?;
+        if let Some(slave) = reg.mirror_slave(args.mirror) {
+            send_rate(rate, slave, args.show_timing)?;
+        }
+    }
+    for watch in reg.watched.iter_mut() {
+        let rate = match reconciled_rate(&watch.ctl_data.name, read_rate(&watch.ctl_data), devname)? {
+            Some(rate) => rate,
+            None => continue,
+        };
+        if rate != watch.exec_data.last_rate.load(Ordering::SeqCst) {
+            warn!("{}: Rate {} read without ctl event within {}ms, reconciling", watch.exec_data.dir, rate, args.event_idle_ms);
+            send_rate(rate, &mut watch.exec_data, args.show_timing)?;
+        }
+    }
+    Ok(())
+}
+
+// keyed by the resolved card, the same for all ways of naming it
+fn lock_ctl(args: &Args, devname: &str, ctl_data: &Option<CtlData>) -> Result<Option<ctl_lock::CtlLock>> {
+    match ctl_data {
+        // only printing, the running instance is not disturbed
+        Some(_) if args.print_resolved => Ok(None),
+        Some(ctl_data) => ctl_lock::lock_ctl(&args.lock_dir, devname, ctl_data.numid, &ctl_data.name),
+        None => Ok(None),
+    }
+}
+
+fn drop_privileges(args: &Args) -> Result<()> {
+    // once, the loops are re-entered unprivileged after the card re-appears
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+    if (args.user.is_none() && args.group.is_none()) || DROPPED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    privileges::drop_privileges(args.user.as_deref(), args.group.as_deref())
+}
+
+// logging the TLV of a rate ctl, the optional command run by the exec thread like the hooks
+fn handle_tlv_change(devname: &str, exec_data: &ExecLocData, numid: u32, run_cmd: bool) -> Result<()> {
+    let tlv = match ctl_tlv::read_tlv(devname, numid) {
+        Ok(tlv) => ctl_tlv::format_tlv(&tlv),
+        Err(err) => {
+            warn!("{}: TLV changed, error: {}", exec_data.dir, err);
+            return Ok(());
+        }
+    };
+    info!("{}: TLV of ctl num ID {} changed: {}", exec_data.dir, numid, tlv);
+    if run_cmd {
+        exec_data.executor.send(Msg::Tlv(tlv))?;
+    }
+    Ok(())
+}
+
+// feeding traced events to dry-run executors, keeping the original timing
+fn replay(path: &str, args: &Args, spawn_cfg: &SpawnCfg, exec_cfg: ExecCfg) -> Result<()> {
+    let events = event_trace::read_trace(path)?;
+    info!("Replaying {} events from {}", events.len(), path);
+    let mut c_exec_data = init_executor(capture_cfg(args)?, spawn_cfg, exec_cfg.clone())?;
+    let mut p_exec_data = init_executor(playback_cfg(args)?, spawn_cfg, exec_cfg.clone())?;
+    let mut last_ts_ms = None;
+    for event in events {
+        if let Some(last_ts_ms) = last_ts_ms {
+            let pause = Duration::from_millis(event.ts_ms.saturating_sub(last_ts_ms));
+            if let Some(Wake::Shutdown) = signals::wait_wake(pause)? {
+                break;
+            }
+        }
+        last_ts_ms = Some(event.ts_ms);
+        trace!("Replayed event: elem num ID {}, index {}, mask {}", event.numid, event.index, event.mask);
+        let (exec_data, slave) = match (event.dir.as_deref(), args.mirror) {
+            (Some("Capture"), None) => (&mut c_exec_data, None),
+            (Some("Playback"), None) => (&mut p_exec_data, None),
+            (Some("Capture"), Some(Direction::Capture)) => (&mut c_exec_data, Some(&mut p_exec_data)),
+            (Some("Playback"), Some(Direction::Playback)) => (&mut p_exec_data, Some(&mut c_exec_data)),
+            // ignored ctl of the mirror mode
+            _ => continue,
+        };
+        if let Some(rate) = event.rate {
+            send_rate(rate, exec_data, args.show_timing)?;
+            if let Some(slave) = slave {
+                send_rate(rate, slave, args.show_timing)?;
+            }
+        }
+    }
+    // letting pending debouncing/stop grace finish
+    thread::sleep(Duration::from_millis((exec_cfg.max_debounce() + exec_cfg.stop_window()) as u64 + 100));
+    for mut exec_data in [c_exec_data, p_exec_data] {
+        exec_data.executor.quit()?;
+    }
+    info!("Replay finished");
+    Ok(())
+}
+
+// flapping rates fed through the full decision pipeline, checking the settled state
+fn soak(args: &Args, spawn_cfg: &SpawnCfg, exec_cfg: ExecCfg) -> Result<()> {
+    info!("Soak test: {} cycles of rates {:?} every {}ms", args.soak_cycles, args.soak, args.soak_interval_ms);
+    let mut execs = vec![init_executor(capture_cfg(args)?, spawn_cfg, exec_cfg.clone())?,
+                         init_executor(playback_cfg(args)?, spawn_cfg, exec_cfg.clone())?];
+    let watches: Vec<_> = execs.iter().map(|exec_data| exec_data.executor.state().watch()).collect();
+    let interval = Duration::from_millis(args.soak_interval_ms as u64);
+    let mut sent = 0;
+    'cycles: for _ in 0..args.soak_cycles {
+        for &rate in &args.soak {
+            for exec_data in execs.iter_mut() {
+                send_rate(rate, exec_data, args.show_timing)?;
+            }
+            sent += 1;
+            if let Some(Wake::Shutdown) = signals::wait_wake(interval)? {
+                break 'cycles;
+            }
+        }
+    }
+    // letting pending debouncing/stop grace finish
+    thread::sleep(Duration::from_millis((exec_cfg.max_debounce() + exec_cfg.stop_window()) as u64 + 100));
+    let last_rate = args.soak.last().copied().unwrap_or(0);
+    let expected = match last_rate {
+        0 => ExecState::Stopped,
+        rate => ExecState::Running(rate),
+    };
+    let mut missed = 0;
+    for (exec_data, watch) in execs.iter().zip(&watches) {
+        let state = exec_data.executor.state().get();
+        let transitions = watch.try_iter().count();
+        // a disabled direction never runs
+        if state != expected && exec_data.enabled.load(Ordering::SeqCst) {
+            missed += 1;
+            warn!("{}: Soak settled in state {}, expected {}", exec_data.dir, state, expected);
+        }
+        info!("{}: Soak: {} rates sent, {} state transitions, settled in {}", exec_data.dir, sent, transitions, state);
+    }
+    for mut exec_data in execs {
+        exec_data.executor.quit()?;
+    }
+    match missed {
+        0 => {
+            info!("Soak test passed");
+            Ok(())
+        }
+        missed => Err(anyhow!("Soak test failed, {} direction(s) missed the final transition", missed)),
+    }
+}
+
+// fallback for drivers with misbehaving event subscription
+// initial = acting on the rates already set, dispatched by the first poll
+fn poll_loop<'a>(h: &'a HCtl, devname: &str, args: &Args, reg: &mut Registry<'a>, initial: bool) -> Result<()> {
+    debug!("Polling rate ctls every {}ms", args.poll_interval_ms);
+    if !initial {
+        reg.seed_polled_rates();
+    }
+    drop_privileges(args)?;
+    let interval = Duration::from_millis(args.poll_interval_ms as u64);
+    loop {
+        let mut changed = None;
+        if let (Some(ctl_data), Some(exec_data)) = (reg.c_ctl_data.as_ref(), reg.c_exec_data.as_mut()) {
+            changed = poll_rate(ctl_data, exec_data, args.show_timing)?;
+        }
+        if let (Some(ctl_data), Some(exec_data)) = (reg.p_ctl_data.as_ref(), reg.p_exec_data.as_mut()) {
+            changed = changed.or(poll_rate(ctl_data, exec_data, args.show_timing)?);
+        }
+        if let (Some(rate), Some(slave)) = (changed, reg.mirror_slave(args.mirror)) {
+            send_rate(rate, slave, args.show_timing)?;
+        }
+        for watch in reg.watched.iter_mut() {
+            poll_rate(&watch.ctl_data, &mut watch.exec_data, args.show_timing)?;
+        }
+        match signals::wait_wake(interval)? {
+            Some(Wake::Shutdown) => {
+                info!("Poll loop finished");
+                return Ok(());
+            }
+            Some(Wake::Reload) => {
+                if let Err(err) = reload(h, devname, args, reg) {
+                    warn!("Reload failed, keeping the current config, error: {:#}", err);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+// re-reading the config file and the command line, the ctl subscription and the lock are kept
+fn reload<'a>(h: &'a HCtl, devname: &str, args: &Args, reg: &mut Registry<'a>) -> Result<()> {
+    let cmd = Args::command();
+    let new_args = Args::from_arg_matches(&with_config(&cmd, cmd.clone().try_get_matches()?)?)?;
+    watch::check_labels(&new_args.watch)?;
+    let exec_cfg = build_exec_cfg(&new_args);
+    // the running watchdog keeps its period
+    check_watchdog(args.watchdog_ms, &exec_cfg)?;
+    let spawn_cfg = SpawnCfg { card: card_id(devname)?, ..build_spawn_cfg(&new_args) };
+    // all updates prepared first, any failure keeps the whole current config
+    let mut dirs = Vec::new();
+    for (dir_cfg, dir) in [(capture_cfg(&new_args)?, Direction::Capture), (playback_cfg(&new_args)?, Direction::Playback)] {
+        if reg.exec_of(dir).is_none() {
+            continue;
+        }
+        usable_cmds(&dir_cfg, &spawn_cfg, true)?;
+        let update = exec_update(&dir_cfg, &spawn_cfg, exec_cfg.clone())?;
+        // mirroring direction has no own ctl
+        let resolved = match reg.ctl_of(dir) {
+            Some(current) => match resolve_ctl(h, devname, &new_args, dir)? {
+                Some(resolved) => Some(resolved),
+                None => {
+                    let name = match dir {
+                        Direction::Capture => &new_args.cctl,
+                        Direction::Playback => &new_args.pctl,
+                    };
+                    warn!("{:?}: Ctl '{}' not found, keeping ctl '{}'", dir, name, current.name);
+                    None
+                }
+            },
+            None => None,
+        };
+        dirs.push((dir, dir_cfg, update, resolved));
+    }
+    // commands of the watches with the same label, the watched ctls are kept
+    let mut watches = Vec::new();
+    for (index, spec) in new_args.watch.iter().enumerate() {
+        let dir_cfg = watch_cfg(&new_args, spec, index)?;
+        match reg.watched.iter().position(|w| w.exec_data.dir == dir_cfg.dir) {
+            Some(pos) => {
+                usable_cmds(&dir_cfg, &spawn_cfg, true)?;
+                let update = exec_update(&dir_cfg, &spawn_cfg, exec_cfg.clone())?;
+                watches.push((pos, dir_cfg, update));
+            }
+            None => warn!("{}: Watch '{}' added, handled only after a restart", dir_cfg.dir, spec.name),
+        }
+    }
+    for watch in reg.watched.iter().filter(|w| !watches.iter().any(|(_, cfg, _)| cfg.dir == w.exec_data.dir)) {
+        warn!("{}: Watch removed, kept running with its previous commands until a restart", watch.exec_data.dir);
+    }
+
+    let mut changed = None;
+    for (dir, dir_cfg, update, resolved) in dirs {
+        let exec_data = match dir {
+            Direction::Capture => reg.c_exec_data.as_mut(),
+            Direction::Playback => reg.p_exec_data.as_mut(),
+        };
+        let exec_data = match exec_data {
+            Some(exec_data) => exec_data,
+            None => continue,
+        };
+        // the commands first, a rate of a new ctl starts them
+        exec_data.restart_same_rate = dir_cfg.restart_same_rate;
+        exec_data.rate_file = dir_cfg.rate_file.clone();
+        *exec_data.cmds.lock().unwrap() = update.cmds.clone();
+        exec_data.executor.send(Msg::Reload(Box::new(update)))?;
+        let (resolved, current) = match (resolved, dir) {
+            (Some(resolved), Direction::Capture) => (resolved, reg.c_ctl_data.as_mut()),
+            (Some(resolved), Direction::Playback) => (resolved, reg.p_ctl_data.as_mut()),
+            (None, _) => continue,
+        };
+        let current = match current {
+            Some(current) => current,
+            None => continue,
+        };
+        if resolved.numid != current.numid {
+            info!("{}: Rate ctl changed to '{}' num ID {}", exec_data.dir, resolved.name, resolved.numid);
+        }
+        *current = resolved;
+        // also the mapping may have changed, an unreadable ctl is picked up by its next event
+        let rate = match read_rate(current) {
+            Ok(rate) => rate,
+            Err(err) => {
+                warn!("{}: Cannot read ctl '{}' after the reload, error: {:#}", exec_data.dir, current.name, err);
+                continue;
+            }
+        };
+        exec_data.polled_rate = rate;
+        if rate != exec_data.last_rate.load(Ordering::SeqCst) {
+            send_rate(rate, exec_data, args.show_timing)?;
+            changed = Some(rate);
+        }
+    }
+    if let (Some(rate), Some(slave)) = (changed, reg.mirror_slave(args.mirror)) {
+        send_rate(rate, slave, args.show_timing)?;
+    }
+    for (pos, dir_cfg, update) in watches {
+        let watch = &mut reg.watched[pos];
+        watch.exec_data.restart_same_rate = dir_cfg.restart_same_rate;
+        *watch.exec_data.cmds.lock().unwrap() = update.cmds.clone();
+        watch.exec_data.executor.send(Msg::Reload(Box::new(update)))?;
+    }
+    info!("Config reloaded");
+    Ok(())
+}
+
+// returns the rate if changed
+fn poll_rate(ctl_data: &CtlData, data: &mut ExecLocData, show_timing: bool) -> Result<Option<usize>> {
+    let rate = read_rate(ctl_data)?;
+    if rate == data.polled_rate {
+        return Ok(None);
+    }
+    data.polled_rate = rate;
+    send_rate(rate, data, show_timing)?;
+    Ok(Some(rate))
+}
+
+// watched ctls mapped to their executors
+struct Registry<'a> {
+    c_ctl_data: Option<CtlData<'a>>,
+    c_exec_data: Option<ExecLocData>,
+    p_ctl_data: Option<CtlData<'a>>,
+    p_exec_data: Option<ExecLocData>,
+    // extra ctls of --watch
+    watched: Vec<Watched<'a>>,
+}
+
+impl<'a> Registry<'a> {
+    fn ctl_of(&self, dir: Direction) -> Option<&CtlData<'a>> {
+        match dir {
+            Direction::Capture => self.c_ctl_data.as_ref(),
+            Direction::Playback => self.p_ctl_data.as_ref(),
+        }
+    }
+
+    fn exec_of(&mut self, dir: Direction) -> Option<&mut ExecLocData> {
+        match dir {
+            Direction::Capture => self.c_exec_data.as_mut(),
+            Direction::Playback => self.p_exec_data.as_mut(),
+        }
+    }
+
+    // executor following the master direction in the mirror mode
+    fn mirror_slave(&mut self, mirror: Option<Direction>) -> Option<&mut ExecLocData> {
+        match mirror {
+            Some(Direction::Capture) => self.p_exec_data.as_mut(),
+            Some(Direction::Playback) => self.c_exec_data.as_mut(),
+            None => None,
+        }
+    }
+
+    // starting the execs for the nonzero rates already set, without waiting for a ctl event
+    fn send_current_rates(&mut self, args: &Args) -> Result<()> {
+        for dir in [Direction::Capture, Direction::Playback] {
+            let rate = match self.ctl_of(dir) {
+                Some(ctl_data) => initial_rate(&ctl_data.name, read_rate(ctl_data)),
+                None => continue,
+            };
+            if let Some(rate) = rate {
+                send_rate(rate, self.exec_of(dir).unwrap(), args.show_timing)?;
+                if let Some(slave) = self.mirror_slave(args.mirror) {
+                    send_rate(rate, slave, args.show_timing)?;
+                }
+            }
+        }
+        for watch in self.watched.iter_mut() {
+            if let Some(rate) = initial_rate(&watch.ctl_data.name, read_rate(&watch.ctl_data)) {
+                send_rate(rate, &mut watch.exec_data, args.show_timing)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the current rates taken as already polled
+    // unreadable ones left to the first poll
+    fn seed_polled_rates(&mut self) {
+        let pairs = [(self.c_ctl_data.as_ref(), self.c_exec_data.as_mut()), (self.p_ctl_data.as_ref(), self.p_exec_data.as_mut())]
+            .into_iter()
+            .filter_map(|(ctl_data, exec_data)| ctl_data.zip(exec_data))
+            .chain(self.watched.iter_mut().map(|w| (&w.ctl_data, &mut w.exec_data)));
+        for (ctl_data, exec_data) in pairs {
+            match read_rate(ctl_data) {
+                Ok(rate) => exec_data.polled_rate = rate,
+                Err(err) => warn!("{}: Cannot read the initial rate, left to the first poll, error: {:#}", exec_data.dir, err),
+            }
         }
     }
+
+    // quitting all exec threads, each killing and reaping its children
+    fn shutdown(&mut self) -> Result<()> {
+        let exec_datas = self.c_exec_data.iter_mut()
+            .chain(self.p_exec_data.iter_mut())
+            .chain(self.watched.iter_mut().map(|w| &mut w.exec_data));
+        quit_execs(exec_datas)
+    }
+
+    fn exec_datas(&self) -> impl Iterator<Item = &ExecLocData> {
+        [&self.c_exec_data, &self.p_exec_data].into_iter()
+            .filter_map(|exec_data| exec_data.as_ref())
+            .chain(self.watched.iter().map(|w| &w.exec_data))
+    }
+
+    // executors without the ctls of the removed card
+    fn detach(self) -> Detached {
+        let watched = self.watched.into_iter().map(|w| (w.exec_data, w._lock)).collect();
+        Detached { c_exec_data: self.c_exec_data, p_exec_data: self.p_exec_data, watched }
+    }
+}
+
+// each exec thread bounded by its quit window, the final events and counters written before the last line
+fn quit_execs<'e>(exec_datas: impl Iterator<Item = &'e mut ExecLocData>) -> Result<()> {
+    info!("Stopping all execs");
+    let mut failed = 0;
+    for exec_data in exec_datas {
+        if let Err(err) = exec_data.executor.quit() {
+            warn!("{:#}", err);
+            failed += 1;
+        }
+        info!("{}: Final counters: {}", exec_data.dir, metrics::format_counters(&exec_data.handle()));
+    }
+    info!("All execs stopped");
+    match failed {
+        0 => Ok(()),
+        failed => Err(anyhow!("{} exec thread(s) failed", failed)),
+    }
+}
+
+// executors kept running over the card removal, the ctls re-resolved once the card re-appears
+struct Detached {
+    c_exec_data: Option<ExecLocData>,
+    p_exec_data: Option<ExecLocData>,
+    watched: Vec<(ExecLocData, Option<ctl_lock::CtlLock>)>,
+}
+
+impl Detached {
+    fn stop(&mut self) -> Result<()> {
+        let exec_datas = self.c_exec_data.iter_mut()
+            .chain(self.p_exec_data.iter_mut())
+            .chain(self.watched.iter_mut().map(|(exec_data, _)| exec_data));
+        for exec_data in exec_datas {
+            exec_data.stop_now()?;
+        }
+        Ok(())
+    }
+
+    // quitting the exec threads when the card never re-appeared
+    fn shutdown(mut self) -> Result<()> {
+        let exec_datas = self.c_exec_data.iter_mut()
+            .chain(self.p_exec_data.iter_mut())
+            .chain(self.watched.iter_mut().map(|(exec_data, _)| exec_data));
+        quit_execs(exec_datas)
+    }
+
+    fn attach<'a>(self, h: &'a HCtl, devname: &str, args: &Args) -> Result<Registry<'a>> {
+        let mut reg = Registry {
+            c_ctl_data: None,
+            c_exec_data: self.c_exec_data,
+            p_ctl_data: None,
+            p_exec_data: self.p_exec_data,
+            watched: Vec::new(),
+        };
+        // mirroring direction has no own ctl
+        if reg.c_exec_data.is_some() && args.mirror != Some(Direction::Playback) {
+            reg.c_ctl_data = reattach_ctl(h, devname, args, Direction::Capture)?;
+        }
+        if reg.p_exec_data.is_some() && args.mirror != Some(Direction::Capture) {
+            reg.p_ctl_data = reattach_ctl(h, devname, args, Direction::Playback)?;
+        }
+        let enum_rate_map: HashMap<String, usize> = args.enum_rate_map.iter().cloned().collect();
+        for (mut exec_data, old_lock) in self.watched {
+            let spec = args.watch.iter().enumerate()
+                .find(|(index, spec)| spec.label(*index) == exec_data.dir)
+                .map(|(_, spec)| spec);
+            let mapping = RateMapping::new(spec.and_then(|spec| spec.fixed_rate), false);
+            let ctl_data = match spec {
+                Some(spec) => get_ctl_data(h, devname, &spec.name, (spec.device, spec.subdevice), 0, mapping, &enum_rate_map)?,
+                None => None,
+            };
+            drop(old_lock);
+            let lock = lock_ctl(args, devname, &ctl_data)?;
+            match ctl_data {
+                Some(ctl_data) => reg.watched.push(Watched { ctl_data, exec_data, _lock: lock }),
+                None => {
+                    warn!("{}: Ctl not found after the card re-appeared, not handled", exec_data.dir);
+                    exec_data.executor.quit()?;
+                }
+            }
+        }
+        // rates already set by the host while the card was re-appearing
+        reg.send_current_rates(args)?;
+        Ok(reg)
+    }
+}
+
+fn reattach_ctl<'a>(h: &'a HCtl, devname: &str, args: &Args, dir: Direction) -> Result<Option<CtlData<'a>>> {
+    let ctl_data = resolve_ctl(h, devname, args, dir)?;
+    if ctl_data.is_none() {
+        warn!("{:?}: Ctl not found after the card re-appeared, not handled", dir);
+    }
+    Ok(ctl_data)
 }
 
 #[inline]
-fn fits_numid(ctl_data: &Option<CtlData>, numid: u32) -> bool {
-    ctl_data.is_some() && ctl_data.as_ref().unwrap().numid == numid
-}
-
-fn init_executor(dir: &str, cmd: String, timeout: usize) -> Result<ExecLocData> {
-    let (exec, c_args) = parse_cmd(cmd, dir);
-    let mut cmd_cfg = CmdCfg::new(exec, c_args);
-    let (timer, canceller) = Timer::new2()?;
-    let (sender, recv) = unbounded();
-    let debouncing = Arc::new(AtomicBool::new(false));
-    let mut thread_data = ExecData::new(dir, timer, timeout, debouncing.clone(), recv.clone());
-    thread::Builder::new()
-        .name(format!("{} Thread", dir))
-        .spawn(move || {
-            executor::run_exec_thread(&mut thread_data, &mut cmd_cfg).unwrap();
-        })?;
-    let data = ExecLocData::new(dir, canceller, debouncing, sender, recv);
-    Ok(data)
-}
-
-fn get_ctl_data<'a>(h: &'a HCtl, elem_name: &'a str) -> Result<Option<CtlData<'a>>> {
-    return match get_elem(elem_name, &h)? {
-        Some(elem) => {
-            let numid = elem.get_id()?.get_numid();
-            debug!("{} id {}", elem_name, numid);
-            Ok(Some(CtlData { elem, numid }))
-        }
-        None => Ok(None)
+fn fits_numid(ctl_data: &Option<CtlData>, numid: u32, index: u32) -> bool {
+    ctl_data.as_ref().is_some_and(|ctl_data| fits_ctl(ctl_data, numid, index))
+}
+
+// the ctl with its secondary
+fn numids(ctl_data: &CtlData) -> Vec<u32> {
+    std::iter::once(ctl_data.numid).chain(ctl_data.secondary.iter().flat_map(|secondary| numids(secondary))).collect()
+}
+
+// extra ctls with their executors, missing ctls and those already handled skipped
+fn init_watches<'a>(h: &'a HCtl, devname: &str, args: &Args, spawn_cfg: &SpawnCfg, exec_cfg: &ExecCfg,
+                    mut taken: Vec<u32>) -> Result<Vec<Watched<'a>>> {
+    let enum_rate_map: HashMap<String, usize> = args.enum_rate_map.iter().cloned().collect();
+    let mut watched = Vec::new();
+    for (index, spec) in args.watch.iter().enumerate() {
+        let dir_cfg = watch_cfg(args, spec, index)?;
+        let mapping = RateMapping::new(spec.fixed_rate, false);
+        let ctl_data = match get_ctl_data(h, devname, &spec.name, (spec.device, spec.subdevice), 0, mapping, &enum_rate_map)? {
+            Some(ctl_data) => ctl_data,
+            None => {
+                info!("{}: Ctl '{}' not found, not handled", dir_cfg.dir, spec.name);
+                continue;
+            }
+        };
+        if numids(&ctl_data).iter().any(|numid| taken.contains(numid)) {
+            warn!("{}: Ctl '{}' num ID {} already handled, ignored", dir_cfg.dir, ctl_data.name, ctl_data.numid);
+            continue;
+        }
+        if !usable_cmds(&dir_cfg, spawn_cfg, args.strict)? {
+            continue;
+        }
+        taken.extend(numids(&ctl_data));
+        let lock = match args.print_resolved {
+            true => None,
+            false => ctl_lock::lock_ctl(&args.lock_dir, devname, ctl_data.numid, &ctl_data.name)?,
+        };
+        info!("{}: ctl '{}' num ID {}, executor started", dir_cfg.dir, ctl_data.name, ctl_data.numid);
+        let exec_data = init_executor(dir_cfg, spawn_cfg, exec_cfg.clone())?;
+        watched.push(Watched { ctl_data, exec_data, _lock: lock });
+    }
+    Ok(watched)
+}
+
+// exec thread config of the direction, also sent on reload
+fn exec_update(dir_cfg: &DirCfg, spawn_cfg: &SpawnCfg, exec_cfg: ExecCfg) -> Result<ExecUpdate> {
+    let dir = dir_cfg.dir.as_str();
+    let spawn_cfg = &SpawnCfg {
+        rate_scale: dir_cfg.rate_scale,
+        device: dir_cfg.coords.0,
+        subdevice: dir_cfg.coords.1,
+        ..spawn_cfg.clone()
+    };
+    let cmd = |argv: &Vec<String>| -> Result<CmdCfg> {
+        let (exec, c_args) = parse_argv(argv.clone(), dir)?;
+        Ok(CmdCfg::new(exec, c_args, spawn_cfg.clone()))
     };
+    let cmds = dir_cfg.cmds.iter().map(|argv| cmd(argv).map(|cmd| cmd.in_shell(dir_cfg.shell_cmds)))
+        .chain(dir_cfg.rate_cmds.iter().map(|(rates, argv)| cmd(argv).map(|cmd| cmd.for_rates(rates.clone()).in_shell(dir_cfg.shell))))
+        .collect::<Result<Vec<CmdCfg>>>()?;
+    let hook = |cmd: &Option<String>| cmd.clone().map(|cmd| -> Result<CmdCfg> {
+        let (exec, c_args) = parse_cmd(cmd, dir_cfg.shell, dir)?;
+        Ok(CmdCfg::new(exec, c_args, spawn_cfg.clone()).in_shell(dir_cfg.shell))
+    }).transpose();
+    let hooks = Hooks {
+        onstop: hook(&dir_cfg.onstop)?,
+        onerror: hook(&dir_cfg.onerror)?,
+        idle: hook(&dir_cfg.idlecmd)?,
+        reload: hook(&dir_cfg.reloadcmd)?,
+        tlv: hook(&dir_cfg.tlvcmd)?,
+    };
+    Ok(ExecUpdate { cfg: ExecCfg { pid_file: dir_cfg.pid_file.clone(), camilla: dir_cfg.camilla.clone(), ..exec_cfg }, cmds, hooks })
 }
 
-fn parse_cmd(cmd: String, dir: &str) -> (String, Vec<String>) {
-    let mut split = cmd.split_whitespace();
-    let exec = split.next().expect(format!("Missing {} executable", dir).as_str());
-    let args = split.map(str::to_string).collect();
+fn init_executor(dir_cfg: DirCfg, spawn_cfg: &SpawnCfg, exec_cfg: ExecCfg) -> Result<ExecLocData> {
+    let update = exec_update(&dir_cfg, spawn_cfg, exec_cfg)?;
+    let cmds = Arc::new(Mutex::new(update.cmds.clone()));
+    let executor = Executor::spawn(&dir_cfg.dir, update, dir_cfg.builtin.clone())?;
+    Ok(ExecLocData::new(&dir_cfg, executor, cmds))
+}
+
+// startup summary regardless of verbosity
+fn log_resolution(dir: &str, ctl_data: &Option<CtlData>, exec_data: &Option<ExecLocData>, mirrored: bool) {
+    let ctl = match ctl_data {
+        Some(ctl_data) => format!("ctl '{}' num ID {}", ctl_data.name, ctl_data.numid),
+        None if mirrored => "mirroring, no own ctl".to_string(),
+        None => "no ctl".to_string(),
+    };
+    let executor = if exec_data.is_some() { "executor started" } else { "not handled" };
+    info!("{}: {}, {}", dir, ctl, executor);
+}
+
+fn print_config(cmd: &ClapCommand, matches: &ArgMatches) {
+    print!("{}", format_config(cmd, matches));
+}
+
+// params as parsed from the command line, one TOML key per param, unset options omitted
+fn format_config(cmd: &ClapCommand, matches: &ArgMatches) -> String {
+    let mut config = String::new();
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id();
+        if matches!(id, "help" | "version" | "print-config" | "config") {
+            continue;
+        }
+        let value = if !arg.is_takes_value_set() {
+            match arg.is_multiple_occurrences_set() {
+                true => matches.occurrences_of(id).to_string(),
+                false => matches.is_present(id).to_string(),
+            }
+        } else {
+            let values: Vec<String> = match matches.values_of(id) {
+                Some(values) => values.map(toml_value).collect(),
+                None => continue,
+            };
+            match arg.is_multiple_occurrences_set() {
+                true => format!("[{}]", values.join(", ")),
+                false => values.join(" "),
+            }
+        };
+        config.push_str(&format!("{} = {}\n", id, value));
+    }
+    config
+}
+
+// TOML basic string, Rust debug escapes like \u{1b} are not valid TOML
+fn toml_value(value: &str) -> String {
+    if let Ok(number) = value.parse::<i64>() {
+        return number.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// one-shot printing of the commands for the current rates
+fn print_resolved(reg: &Registry) -> Result<()> {
+    let handled = [(&reg.c_ctl_data, &reg.c_exec_data), (&reg.p_ctl_data, &reg.p_exec_data)].into_iter()
+        .filter_map(|(ctl_data, exec_data)| ctl_data.as_ref().zip(exec_data.as_ref()))
+        .chain(reg.watched.iter().map(|w| (&w.ctl_data, &w.exec_data)));
+    for (ctl_data, exec_data) in handled {
+        exec_data.last_rate.store(read_rate(ctl_data)?, Ordering::SeqCst);
+        print!("{}", socket::format_resolved(&exec_data.handle()));
+    }
+    Ok(())
+}
+
+// {CARD} of the commands, the ID also when the card was given by index or longname
+fn card_id(devname: &str) -> Result<String> {
+    let id = Ctl::new(devname, false)?.card_info()?.get_id()?.to_string();
+    debug!("Card {} has ID {}", devname, id);
+    Ok(id)
+}
+
+// card given by index, ID, name or longname
+// waiting = the card may appear later
+fn card_devname(args: &Args, waiting: bool) -> Result<String> {
+    let name = args.gadget_name.as_str();
+    if let Some(devname) = find_card(name)? {
+        return Ok(devname);
+    }
+    if waiting || args.wait_for_controls_ms > 0 {
+        debug!("Card '{}' not present yet", name);
+        return Ok(format!("hw:{}", name));
+    }
+    let available: Vec<String> = alsa::card::Iter::new()
+        .filter_map(|card| card.ok())
+        .map(|card| format!("{}: {} ({})", card.get_index(), card.get_name().unwrap_or_default(),
+                            card.get_longname().unwrap_or_default()))
+        .collect();
+    let available = match available.is_empty() {
+        true => "none".to_string(),
+        false => available.join(", "),
+    };
+    Err(Error::CardNotFound { name: name.to_string(), available }.into())
+}
+
+// hw:INDEX of the present card, None = not present
+fn find_card(name: &str) -> Result<Option<String>> {
+    // index or ID resolved by alsa
+    if let Ok(card) = alsa::Card::from_str(&CString::new(name)?) {
+        return Ok(Some(format!("hw:{}", card.get_index())));
+    }
+    for card in alsa::card::Iter::new() {
+        let card = card?;
+        if card.get_name().is_ok_and(|short| short == name) || card.get_longname().is_ok_and(|long| long == name) {
+            debug!("Card '{}' resolved to index {}", name, card.get_index());
+            return Ok(Some(format!("hw:{}", card.get_index())));
+        }
+    }
+    Ok(None)
+}
+
+// retrying with backoff until a rate ctl is found or the wait elapses
+// the card resolved at each attempt, it may appear at another index or only later by its name
+// None = shutdown requested while waiting
+fn open_hctl(args: &Args, wait_forever: bool) -> Result<Option<(String, HCtl)>> {
+    let deadline = Instant::now() + Duration::from_millis(args.wait_for_controls_ms as u64);
+    let mut backoff = Backoff::new(args.backoff_base_ms, args.backoff_max_ms);
+    loop {
+        let expired = !wait_forever && Instant::now() >= deadline;
+        let devname = find_card(&args.gadget_name)?.unwrap_or_else(|| format!("hw:{}", args.gadget_name));
+        match load_hctl(&devname, nonblocking(args)) {
+            Ok(h) => {
+                if expired || has_rate_ctl(&h, args)? {
+                    return Ok(Some((devname, h)));
+                }
+                debug!("Rate ctls of {} not found yet", devname);
+            }
+            Err(err) if expired => return Err(err.into()),
+            Err(err) => debug!("{:#}, retrying", anyhow::Error::from(err)),
+        }
+        let delay = backoff.next_delay();
+        info!("Waiting {} ms for the rate ctls of {}", delay.as_millis(), devname);
+        if signals::shutdown_within(delay)? {
+            info!("Shutdown requested while waiting for {}", devname);
+            return Ok(None);
+        }
+    }
+}
+
+// only the blocking event mode waits in read, polling must never block on the handles
+fn nonblocking(args: &Args) -> bool {
+    args.poll_interval_ms > 0
+}
+
+fn load_hctl(devname: &str, nonblock: bool) -> Result<HCtl, Error> {
+    let unavailable = |source| Error::CardUnavailable { devname: devname.to_string(), source };
+    let h = HCtl::new(devname, nonblock).map_err(unavailable)?;
+    h.load().map_err(unavailable)?;
+    Ok(h)
+}
+
+fn has_rate_ctl(h: &HCtl, args: &Args) -> Result<bool> {
+    Ok(find_elem(&args.cctl, (args.cdevice, args.csubdevice), h)?.is_some()
+        || find_elem(&args.pctl, (args.pdevice, args.psubdevice), h)?.is_some())
+}
+
+// primary ctl of the direction with its optional secondary
+fn resolve_ctl<'a>(h: &'a HCtl, devname: &str, args: &Args, dir: Direction) -> Result<Option<CtlData<'a>>> {
+    let enum_rate_map: HashMap<String, usize> = args.enum_rate_map.iter().cloned().collect();
+    let (name, secondary, coords, value_index, mapping) = match dir {
+        Direction::Capture => (&args.cctl, &args.cctl2, (args.cdevice, args.csubdevice), args.cctl_index,
+                               RateMapping::new(args.cfixed_rate, args.cinvert)),
+        Direction::Playback => (&args.pctl, &args.pctl2, (args.pdevice, args.psubdevice), args.pctl_index,
+                                RateMapping::new(args.pfixed_rate, args.pinvert)),
+    };
+    let mut ctl_data = get_ctl_data(h, devname, name, coords, value_index, mapping, &enum_rate_map)?;
+    if let (Some(ctl_data), Some(name)) = (ctl_data.as_mut(), secondary) {
+        attach_secondary(ctl_data, h, devname, name, coords, &enum_rate_map)?;
+    }
+    Ok(ctl_data)
+}
+
+// missing executable disables the direction, or refuses to start in strict mode
+fn usable_cmds(dir_cfg: &DirCfg, spawn_cfg: &SpawnCfg, strict: bool) -> Result<bool> {
+    if spawn_cfg.dry_run || dir_cfg.builtin.is_some() || dir_cfg.camilla.is_some() {
+        return Ok(true);
+    }
+    // with --shell only sh itself, the commands of the script fail at start
+    // exec with a placeholder is checked at start
+    let missing = |argv: &Vec<String>| argv.first()
+        .filter(|exec| !spawn_cfg.has_placeholder(exec) && !executor::exec_exists(exec))
+        .cloned();
+    // a rate-specific command fails only at its rates
+    for (rates, exec) in dir_cfg.rate_cmds.iter().filter_map(|(rates, argv)| missing(argv).map(|exec| (rates, exec))) {
+        if strict {
+            return Err(Error::ExecNotFound(exec).into());
+        }
+        let rates: Vec<String> = rates.iter()
+            .map(|(low, high)| if low == high { low.to_string() } else { format!("{}-{}", low, high) })
+            .collect();
+        warn!("{}: Executable {} not found, starts at rates {} will fail", dir_cfg.dir, exec, rates.join(","));
+    }
+    match dir_cfg.cmds.iter().find_map(missing) {
+        Some(exec) if strict => Err(Error::ExecNotFound(exec).into()),
+        Some(exec) => {
+            warn!("{}: Executable {} not found, {} will not be handled", dir_cfg.dir, exec, dir_cfg.dir.to_lowercase());
+            Ok(false)
+        }
+        None => Ok(true),
+    }
+}
+
+// command file takes precedence over the literal args, they over the commands in args
+fn get_cmds(cmd_file: &Option<String>, cmds: &[String], argv: &[String], shell: bool) -> Result<Vec<Vec<String>>> {
+    match cmd_file {
+        Some(path) => Ok(vec![split_cmd(&read_cmd_file(path)?, shell).with_context(|| format!("Invalid command file {}", path))?]),
+        None if !argv.is_empty() => Ok(vec![argv.to_vec()]),
+        None => cmds.iter().map(|cmd| split_cmd(cmd, shell)).collect(),
+    }
+}
+
+fn split_rate_cmds(rate_cmds: &[(RateRanges, String)], shell: bool) -> Result<Vec<(RateRanges, Vec<String>)>> {
+    rate_cmds.iter().map(|(rates, cmd)| Ok((rates.clone(), split_cmd(cmd, shell)?))).collect()
+}
+
+// sh-like quoting without any expansion, or the whole command for sh -c
+fn split_cmd(cmd: &str, shell: bool) -> Result<Vec<String>> {
+    if shell {
+        cmdline::check(cmd)?;
+        return Ok(vec!["sh".to_string(), "-c".to_string(), cmd.to_string()]);
+    }
+    cmdline::split(cmd)
+}
+
+fn read_cmd_file(path: &str) -> Result<String> {
+    let content = fs::read_to_string(path).with_context(|| format!("Cannot read command file {}", path))?;
+    let cmd = content.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        // trailing backslash of shell-style continuation is optional
+        .map(|line| line.strip_suffix('\\').unwrap_or(line).trim_end())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if cmd.is_empty() {
+        return Err(anyhow!("Command file {} contains no command", path));
+    }
+    debug!("Command from {}: {}", path, cmd);
+    Ok(cmd)
+}
+
+// quoting of the command params checked by clap, also of the reloaded config
+fn parse_cmd(cmd: String, shell: bool, dir: &str) -> Result<(String, Vec<String>)> {
+    parse_argv(split_cmd(&cmd, shell).with_context(|| format!("Invalid {} command", dir))?, dir)
+}
+
+// first item is the executable
+fn parse_argv(mut argv: Vec<String>, dir: &str) -> Result<(String, Vec<String>)> {
+    if argv.is_empty() {
+        return Err(anyhow!("Missing {} executable", dir));
+    }
+    let exec = argv.remove(0);
+    let args = argv;
 
     debug!("{} exec: {:#?}", dir, exec);
     debug!("{} args: {:#?}", dir, args);
-    (exec.to_string(), args)
+    Ok((exec, args))
+}
+
+// name with or without the SIG prefix, or number
+fn parse_signal(s: &str) -> Result<Signal> {
+    if let Ok(num) = s.parse::<i32>() {
+        return Ok(Signal::try_from(num)?);
+    }
+    let name = s.to_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    Signal::from_str(&name).map_err(|_| anyhow!("Unknown signal '{}'", s))
+}
+
+// empty token would match everywhere
+fn parse_rate_token(s: &str) -> Result<String> {
+    match s.is_empty() {
+        true => Err(anyhow!("rate token must not be empty")),
+        false => Ok(s.to_string()),
+    }
+}
+
+// bare number = ms, or with units, e.g. 50ms, 2s, 1m
+fn parse_duration_ms(s: &str) -> Result<usize> {
+    if let Ok(ms) = s.parse() {
+        return Ok(ms);
+    }
+    let duration = humantime::parse_duration(s)
+        .map_err(|err| anyhow!("invalid duration '{}', expected ms or a value with units (e.g. 50ms, 2s, 1m): {}", s, err))?;
+    Ok(duration.as_millis() as usize)
+}
+
+// e.g. 44100,48000=CMD or 352800-768000=CMD
+fn parse_rate_cmd(s: &str) -> Result<(RateRanges, String)> {
+    let (rates, cmd) = s.split_once('=').ok_or_else(|| anyhow!("missing '=' in '{}'", s))?;
+    let rates = rates.split(',')
+        .map(|range| match range.split_once('-') {
+            Some((low, high)) => {
+                let (low, high): (usize, usize) = (low.trim().parse()?, high.trim().parse()?);
+                match low <= high {
+                    true => Ok((low, high)),
+                    false => Err(anyhow!("range {} starts above its end", range)),
+                }
+            }
+            None => Ok(range.trim().parse().map(|rate| (rate, rate))?),
+        })
+        .collect::<Result<RateRanges>>()
+        .map_err(|err| anyhow!("invalid rates '{}': {}", rates, err))?;
+    if cmd.trim().is_empty() {
+        return Err(anyhow!("empty command in '{}'", s));
+    }
+    cmdline::check(cmd)?;
+    Ok((rates, cmd.to_string()))
+}
+
+fn parse_key_val<K: FromStr, V: FromStr>(s: &str) -> Result<(K, V)>
+    where K::Err: std::error::Error + Send + Sync + 'static, V::Err: std::error::Error + Send + Sync + 'static {
+    let (key, value) = s.split_once('=').ok_or_else(|| anyhow!("missing '=' in '{}'", s))?;
+    Ok((key.parse()?, value.parse()?))
 }
 
 fn init_logging(args: &Args) {
-    Builder::new()
-        .format(|buf, record| {
+    let journald = args.journald;
+    let level = match args.verbose {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let dir_levels: Vec<(String, LevelFilter)> = [("Playback", args.plog_level), ("Capture", args.clog_level)].iter()
+        .filter_map(|(dir, dir_level)| dir_level.map(|dir_level| (dir.to_string(), dir_level)))
+        .collect();
+    let target_levels = env::var("RUST_LOG").map(|spec| logging::parse_target_levels(&spec)).unwrap_or_default();
+    let max_level = dir_levels.iter().chain(target_levels.iter()).map(|(_, level)| *level).fold(level, Ord::max);
+    let inner = Builder::new()
+        .format(move |buf, record| {
+            if journald {
+                // sd-daemon priority prefix, parsed by journald
+                write!(buf, "<{}>", syslog_priority(record.level()))?;
+            }
             writeln!(buf, "{}", record.args())
         })
-        .filter(None, match args.verbose {
-            0 => LevelFilter::Info,
-            1 => LevelFilter::Debug,
-            _ => LevelFilter::Trace,
-        })
-        .init();
+        // levels filtered by the wrapping logger
+        .filter(None, max_level)
+        .build();
+    let logger = DirLevelLogger::new(inner, level, dir_levels, target_levels);
+    log::set_max_level(max_level);
+    log::set_boxed_logger(Box::new(logger)).expect("Logger already initialized");
 }
 
-fn send_new_rate(elem: &Elem, data: &mut ExecLocData, show_timing: bool) -> Result<()> {
-    let rate = read_value(&elem)?.unwrap() as usize;
+fn syslog_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// same rate after the exec thread stopped the exec itself by the gate or the max runtime,
+// the failed one stays stopped until a real rate change
+fn redispatch(state: ExecState, rate: usize, dispatched_rate: Option<usize>) -> bool {
+    rate > 0 && state == ExecState::Stopped && dispatched_rate == Some(rate)
+}
+
+fn send_rate(rate: usize, data: &mut ExecLocData, show_timing: bool) -> Result<()> {
+    if signals::paused() {
+        debug!("{}: Paused, ignoring new rate value: {}", data.dir, rate);
+        return Ok(());
+    }
     debug!("{}: New rate value: {}", data.dir, rate);
+    data.last_rate.store(rate, Ordering::SeqCst);
+    if data.simulated.swap(false, Ordering::SeqCst) {
+        // the exec thread may run a different rate than last dispatched
+        data.dispatched_rate = None;
+    }
+    if !data.enabled.load(Ordering::SeqCst) {
+        debug!("{}: Disabled, ignoring new rate", data.dir);
+        // enabling sends the rate directly
+        data.dispatched_rate = None;
+        return Ok(());
+    }
+    if redispatch(data.executor.state().get(), rate, data.dispatched_rate) {
+        debug!("{}: Exec not running, dispatching rate {} again", data.dir, rate);
+        data.dispatched_rate = None;
+    }
+    if data.dispatched_rate == Some(rate) {
+        // several events for one change
+        if data.restart_same_rate && rate > 0 {
+            debug!("{}: Same rate {} received, restarting", data.dir, rate);
+            data.executor.send(Msg::Restart)?;
+        } else {
+            trace!("{}: Same rate {} already dispatched, ignoring", data.dir, rate);
+        }
+        return Ok(());
+    }
+    data.dispatched_rate = Some(rate);
     if show_timing {
         print_timing(data, rate)
     }
+    if let Some(path) = &data.rate_file {
+        if let Err(err) = write_rate_file(path, rate) {
+            warn!("{}: Cannot write rate file {}, error: {}", data.dir, path, err);
+        }
+    }
 
     if rate == 0 {
-        // requesting STOP
-        // draining the channel for possible unconsumed requests
-        let drained_cnt = data.draining_recv.try_iter().count();
-        trace!("{}: Drained {} messages", data.dir, drained_cnt);
-        if data.debouncing_now.load(Ordering::SeqCst) {
-            // cancelling the debouncing timer in the exec thread
-            debug!("{}: Cancelling debounce wait", data.dir);
-            data.canceller.cancel()?;
-        }
-        data.sender.send(Msg::StopExec)?;
+        // requesting STOP, a pending debounced start is coalesced by the exec thread
+        data.executor.send(Msg::StopExec)?;
     } else {
-        // sending the required rate
-        data.sender.send(Msg::StartExec(rate))?;
+        // sending the required rate, cancels a pending stop grace in the exec thread
+        data.executor.send(Msg::StartExec(rate))?;
     }
     Ok(())
 }
 
+// readers never see a partially written file
+fn write_rate_file(path: &str, rate: usize) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, format!("{}\n", rate))?;
+    fs::rename(&tmp, path)
+}
+
 fn print_timing(data: &mut ExecLocData, rate: usize) {
-    if rate == 0 && data.last_start.is_some() {
-        let duration = Instant::now() - data.last_start.unwrap();
+    if let (0, Some(last_start)) = (rate, data.last_start) {
+        let duration = Instant::now() - last_start;
         info!("{}: STOP received after {} ms", data.dir, duration.as_millis());
     }
     if rate > 0 {
@@ -240,17 +2149,145 @@ fn print_timing(data: &mut ExecLocData, rate: usize) {
     }
 }
 
-fn get_elem<'a>(elemname: &str, h: &'a HCtl) -> Result<Option<Elem<'a>>> {
-    let mut elid = ElemId::new(ElemIface::PCM);
-    elid.set_device(0);
-    elid.set_subdevice(0);
-    elid.set_name(&CString::new(elemname)?);
-    let elem = h.find_elem(&elid);
-    Ok(elem)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_rate_redispatched_only_when_stopped() {
+        assert!(redispatch(ExecState::Stopped, 48000, Some(48000)));
+        assert!(!redispatch(ExecState::Failed, 48000, Some(48000)));
+        assert!(!redispatch(ExecState::Running(48000), 48000, Some(48000)));
+        assert!(!redispatch(ExecState::Stopped, 0, Some(0)));
+        assert!(!redispatch(ExecState::Stopped, 44100, Some(48000)));
+    }
+
+    fn args(argv: &[&str]) -> Args {
+        Args::try_parse_from([&["gaudio_ctl"], argv].concat()).unwrap()
+    }
 
-fn read_value(elem: &Elem) -> Result<Option<i32>> {
-    let value = elem.read()?;
-    let rate = value.get_integer(0);
-    Ok(rate)
-}
\ No newline at end of file
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("gaudio_ctl_{}_{}", name, std::process::id())).display().to_string()
+    }
+
+    #[test]
+    fn durations_in_ms_or_with_units() {
+        assert_eq!(parse_duration_ms("50").unwrap(), 50);
+        assert_eq!(parse_duration_ms("50ms").unwrap(), 50);
+        assert_eq!(parse_duration_ms("2s").unwrap(), 2000);
+        assert_eq!(parse_duration_ms("1m 30s").unwrap(), 90000);
+        let err = parse_duration_ms("fast").unwrap_err().to_string();
+        assert!(err.starts_with("invalid duration 'fast'"), "{}", err);
+        assert!(parse_duration_ms("-5").is_err());
+    }
+
+    #[test]
+    fn rate_cmds_with_rates_and_ranges() {
+        let (rates, cmd) = parse_rate_cmd("44100, 48000=alsaloop -r {R}").unwrap();
+        assert_eq!(rates, vec![(44100, 44100), (48000, 48000)]);
+        assert_eq!(cmd, "alsaloop -r {R}");
+        let (rates, cmd) = parse_rate_cmd("352800-768000=dsd {R}=x").unwrap();
+        assert_eq!(rates, vec![(352800, 768000)]);
+        assert_eq!(cmd, "dsd {R}=x");
+        let err = |s: &str| parse_rate_cmd(s).unwrap_err().to_string();
+        assert_eq!(err("alsaloop"), "missing '=' in 'alsaloop'");
+        assert!(err("48000-44100=x").contains("range 48000-44100 starts above its end"));
+        assert!(err("44100,fast=x").starts_with("invalid rates '44100,fast'"));
+        assert_eq!(err("44100= "), "empty command in '44100= '");
+        assert!(parse_rate_cmd("44100=cmd 'unclosed").is_err());
+    }
+
+    #[test]
+    fn cmd_file_lines_joined() {
+        let path = temp_path("cmd_file");
+        fs::write(&path, "# loop\nalsaloop -r {R} \\\n  -C hw:UAC2Gadget # capture\n\n  -P hw:Loopback,1\n").unwrap();
+        let cmd = read_cmd_file(&path);
+        fs::write(&path, "# nothing\n\n").unwrap();
+        let empty = read_cmd_file(&path).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cmd.unwrap(), "alsaloop -r {R} -C hw:UAC2Gadget -P hw:Loopback,1");
+        assert_eq!(empty, format!("Command file {} contains no command", path));
+        let missing = read_cmd_file(&path).unwrap_err().to_string();
+        assert_eq!(missing, format!("Cannot read command file {}", path));
+    }
+
+    #[test]
+    fn signals_by_name_or_number() {
+        assert_eq!(parse_signal("TERM").unwrap(), Signal::SIGTERM);
+        assert_eq!(parse_signal("sigint").unwrap(), Signal::SIGINT);
+        assert_eq!(parse_signal("SIGHUP").unwrap(), Signal::SIGHUP);
+        assert_eq!(parse_signal("2").unwrap(), Signal::SIGINT);
+        assert_eq!(parse_signal("FOO").unwrap_err().to_string(), "Unknown signal 'FOO'");
+        assert!(parse_signal("999").is_err());
+    }
+
+    #[test]
+    fn rate_token_not_empty() {
+        assert_eq!(parse_rate_token("{RATE}").unwrap(), "{RATE}");
+        assert_eq!(parse_rate_token("").unwrap_err().to_string(), "rate token must not be empty");
+    }
+
+    #[test]
+    fn missing_exec_disables_direction() {
+        let usable = |argv: &[&str], strict: bool| {
+            let args = args(argv);
+            usable_cmds(&capture_cfg(&args).unwrap(), &build_spawn_cfg(&args), strict)
+        };
+        assert!(usable(&["--ccmd", "sh -c true"], true).unwrap());
+        assert!(!usable(&["--ccmd", "/nonexistent/gaudio_ctl_exec"], false).unwrap());
+        assert!(usable(&["--ccmd", "/nonexistent/gaudio_ctl_exec"], true).is_err());
+        assert!(usable(&["--ccmd", "/nonexistent/gaudio_ctl_exec", "--dry-run"], true).unwrap());
+        // filled in only at start
+        assert!(usable(&["--ccmd", "/opt/{R}/loop"], true).unwrap());
+        // only its rates fail
+        let rate_cmd = ["--ccmd", "sh -c true", "--crate-cmd", "96000=/nonexistent/gaudio_ctl_exec"];
+        assert!(usable(&rate_cmd, false).unwrap());
+        assert!(usable(&rate_cmd, true).is_err());
+    }
+
+    #[test]
+    fn config_printed_as_toml() {
+        let cmd = Args::command();
+        let argv = ["gaudio_ctl", "--timeout", "100", "--ccmd", "a b", "--ccmd", "c", "--dry-run", "-vv"];
+        let config = format_config(&cmd, &cmd.clone().try_get_matches_from(argv).unwrap());
+        let lines: Vec<&str> = config.lines().collect();
+        assert!(lines.contains(&"timeout = 100"), "{}", config);
+        assert!(lines.contains(&"ccmd = [\"a b\", \"c\"]"), "{}", config);
+        assert!(lines.contains(&"dry-run = true"), "{}", config);
+        assert!(lines.contains(&"strict = false"), "{}", config);
+        assert!(lines.contains(&"verbose = 2"), "{}", config);
+        assert!(!lines.iter().any(|line| line.starts_with("socket = ")), "{}", config);
+        assert_eq!(toml_value("48000"), "48000");
+        assert_eq!(toml_value("hw:UAC2Gadget"), "\"hw:UAC2Gadget\"");
+    }
+
+    #[test]
+    fn printed_config_read_back() {
+        let cmd = Args::command();
+        let socket = "/run/a \"b\" \\c\nd\te\u{1}f\u{7f}g\u{e9}";
+        let ccmd = r#"sh -c "echo \"{R}\" > /tmp/rate""#;
+        let argv = ["gaudio_ctl", "--socket", socket, "--ccmd", ccmd, "--ccmd", "alsaloop -r {R}", "--timeout", "100"];
+        let config = format_config(&cmd, &cmd.clone().try_get_matches_from(argv).unwrap());
+        assert!(config.contains("\\u0001f\\u007Fg\u{e9}"), "{}", config);
+        let path = temp_path("config");
+        fs::write(&path, &config).unwrap();
+        let config_args = config::config_args(&path, &cmd, &[]);
+        fs::remove_file(&path).unwrap();
+        let read = Args::try_parse_from([vec!["gaudio_ctl".to_string()], config_args.unwrap()].concat()).unwrap();
+        assert_eq!(read.socket.as_deref(), Some(socket));
+        assert_eq!(read.ccmd, vec![ccmd.to_string(), "alsaloop -r {R}".to_string()]);
+        assert_eq!(read.timeout, 100);
+    }
+
+    #[test]
+    fn watchdog_must_exceed_busy_window() {
+        // 50 ms debouncing, 3 x 5000 hook timeout and 2 x 2000 kill timeout by default
+        let exec_cfg = build_exec_cfg(&args(&[]));
+        let window = exec_cfg.busy_window().unwrap();
+        assert!(check_watchdog(0, &exec_cfg).is_ok());
+        assert!(check_watchdog(window, &exec_cfg).is_err());
+        assert!(check_watchdog(window + 1, &exec_cfg).is_ok());
+        let unlimited = build_exec_cfg(&args(&["--hook-timeout-ms", "0"]));
+        assert!(check_watchdog(60000, &unlimited).is_err());
+    }
+}
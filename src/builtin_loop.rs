@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+use alsa::{Direction, ValueOr};
+use alsa::pcm::{Access, Frames, HwParams, IoFormat, PCM};
+use anyhow::{Context, Result};
+use clap::ArgEnum;
+use log::{debug, info, warn};
+use nix::errno::Errno;
+
+// max wait for capture data, the stop flag is checked in between
+const WAIT_TIMEOUT_MS: u32 = 100;
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopFormat {
+    S16Le,
+    S32Le,
+}
+
+// in-process replacement of alsaloop
+#[derive(Debug, Clone)]
+pub struct LoopCfg {
+    pub capture_dev: String,
+    pub playback_dev: String,
+    pub format: LoopFormat,
+    pub channels: u32,
+    pub period_frames: usize,
+    // only log the start
+    pub dry_run: bool,
+}
+
+// running copy thread, stopped by the executor
+pub struct LoopHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LoopHandle {
+    // copy thread ended on its own, e.g. by a device error
+    pub fn finished(&self) -> bool {
+        self.thread.as_ref().is_some_and(|thread| thread.is_finished())
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for LoopHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// devices are opened by the caller, failing the start like a missing exec
+pub fn start(cfg: &LoopCfg, rate: usize, dir: &str) -> Result<LoopHandle> {
+    let capture = open_pcm(&cfg.capture_dev, Direction::Capture, cfg, rate)?;
+    let playback = open_pcm(&cfg.playback_dev, Direction::Playback, cfg, rate)?;
+    info!("{}: Builtin loop {} -> {} at rate {}", dir, cfg.capture_dev, cfg.playback_dev, rate);
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let thread_dir = dir.to_string();
+    let samples = cfg.period_frames * cfg.channels as usize;
+    let channels = cfg.channels as usize;
+    let format = cfg.format;
+    let thread = thread::Builder::new()
+        .name(format!("{} Loop Thread", dir))
+        .spawn(move || {
+            let result = match format {
+                LoopFormat::S16Le => copy::<i16>(&capture, &playback, channels, samples, &thread_stop, &thread_dir),
+                LoopFormat::S32Le => copy::<i32>(&capture, &playback, channels, samples, &thread_stop, &thread_dir),
+            };
+            if let Err(err) = result {
+                warn!("{}: Builtin loop failed, error: {:#}", thread_dir, err);
+            }
+            let _ = capture.drop();
+            let _ = playback.drop();
+            debug!("{}: Builtin loop finished", thread_dir);
+        })?;
+    Ok(LoopHandle { stop, thread: Some(thread) })
+}
+
+fn open_pcm(name: &str, direction: Direction, cfg: &LoopCfg, rate: usize) -> Result<PCM> {
+    let pcm = PCM::new(name, direction, true).with_context(|| format!("Cannot open PCM {}", name))?;
+    {
+        let hwp = HwParams::any(&pcm)?;
+        hwp.set_channels(cfg.channels)?;
+        hwp.set_rate(rate as u32, ValueOr::Nearest)?;
+        hwp.set_format(match cfg.format {
+            LoopFormat::S16Le => <i16 as IoFormat>::FORMAT,
+            LoopFormat::S32Le => <i32 as IoFormat>::FORMAT,
+        })?;
+        hwp.set_access(Access::RWInterleaved)?;
+        hwp.set_period_size_near(cfg.period_frames as Frames, ValueOr::Nearest)?;
+        hwp.set_buffer_size_near(4 * cfg.period_frames as Frames)?;
+        pcm.hw_params(&hwp).with_context(|| format!("Cannot set params of PCM {} for rate {}", name, rate))?;
+    }
+    pcm.prepare()?;
+    Ok(pcm)
+}
+
+// copying capture periods to playback until stopped, recovering from xruns
+fn copy<S: IoFormat + Default + Copy>(capture: &PCM, playback: &PCM, channels: usize, samples: usize,
+                                      stop: &AtomicBool, dir: &str) -> Result<()> {
+    let cio = capture.io_checked::<S>()?;
+    let pio = playback.io_checked::<S>()?;
+    let mut buf = vec![S::default(); samples];
+    let mut xruns = 0;
+    capture.start()?;
+    while !stop.load(Ordering::SeqCst) {
+        let read = capture.wait(Some(WAIT_TIMEOUT_MS)).and_then(|ready| match ready {
+            true => cio.readi(&mut buf),
+            false => Ok(0),
+        });
+        let frames = match read {
+            Ok(frames) => frames,
+            Err(err) if err.errno() == Errno::EAGAIN => continue,
+            Err(err) => {
+                xruns += 1;
+                warn!("{}: Builtin loop capture xrun #{}, recovering", dir, xruns);
+                capture.try_recover(err, true)?;
+                capture.start()?;
+                continue;
+            }
+        };
+        let mut written = 0;
+        while written < frames && !stop.load(Ordering::SeqCst) {
+            match pio.writei(&buf[written * channels..frames * channels]) {
+                Ok(n) => written += n,
+                Err(err) if err.errno() == Errno::EAGAIN => {
+                    playback.wait(Some(WAIT_TIMEOUT_MS))?;
+                }
+                Err(err) => {
+                    xruns += 1;
+                    warn!("{}: Builtin loop playback xrun #{}, recovering", dir, xruns);
+                    // restarted by the next write
+                    playback.try_recover(err, true)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn handle(body: fn(Arc<AtomicBool>)) -> LoopHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        LoopHandle { stop, thread: Some(thread::spawn(move || body(thread_stop))) }
+    }
+
+    #[test]
+    fn ended_thread_reported_finished() {
+        let handle = handle(|_| {});
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !handle.finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(handle.finished());
+    }
+
+    #[test]
+    fn stop_ends_running_thread() {
+        let mut handle = handle(|stop| while !stop.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(10));
+        });
+        assert!(!handle.finished());
+        handle.stop();
+        assert!(handle.thread.is_none());
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+
+use crate::executor::RateScale;
+use crate::json;
+
+// max wait for connecting, sending a command and for a CamillaDSP reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// replies are short status messages, a larger frame is a broken stream
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+// CamillaDSP websocket server configured instead of the commands
+#[derive(Debug, Clone)]
+pub struct CamillaCfg {
+    // host:port of the websocket server
+    pub addr: String,
+    // config file path, the rate token replaced with the rate
+    pub config: String,
+    // sending the config file contents by SetConfig instead of its path by SetConfigName
+    pub inline: bool,
+    pub rate_token: String,
+    // applied to the rate replacing the token, like for the commands
+    pub rate_scale: RateScale,
+    // only log the commands
+    pub dry_run: bool,
+}
+
+// connection to the running CamillaDSP, processing stopped by the executor
+pub struct CamillaHandle {
+    stream: TcpStream,
+    cfg: CamillaCfg,
+    stopped: bool,
+}
+
+impl CamillaHandle {
+    // loading the config for the new rate within the open connection
+    pub fn set_rate(&mut self, rate: usize, dir: &str) -> Result<()> {
+        let scaled = self.cfg.rate_scale.apply(rate)
+            .ok_or_else(|| anyhow!("Rate {} scaled by {} is not an integer", rate, self.cfg.rate_scale))?
+            .to_string();
+        let path = self.cfg.config.replace(&self.cfg.rate_token, &scaled);
+        let cmds = match self.cfg.inline {
+            true => {
+                let config = fs::read_to_string(&path).with_context(|| format!("Cannot read config {}", path))?;
+                vec![format!("{{\"SetConfig\": {}}}", json::quote(&config.replace(&self.cfg.rate_token, &scaled)))]
+            }
+            false => vec![format!("{{\"SetConfigName\": {}}}", json::quote(&path)), "\"Reload\"".to_string()],
+        };
+        for cmd in cmds {
+            self.command(&cmd)?;
+        }
+        info!("{}: CamillaDSP {} switched to config {} at rate {}", dir, self.cfg.addr, path, rate);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if self.stopped {
+            return;
+        }
+        self.stopped = true;
+        if let Err(err) = self.command("\"Stop\"") {
+            warn!("CamillaDSP {}: Cannot stop processing, error: {:#}", self.cfg.addr, err);
+        }
+        let _ = write_frame(&mut self.stream, OPCODE_CLOSE, &[]);
+    }
+
+    fn command(&mut self, cmd: &str) -> Result<()> {
+        debug!("CamillaDSP {} command {}", self.cfg.addr, cmd);
+        write_frame(&mut self.stream, OPCODE_TEXT, cmd.as_bytes())?;
+        let reply = read_reply(&mut self.stream)?;
+        match reply_ok(&reply, cmd) {
+            true => Ok(()),
+            false => Err(anyhow!("CamillaDSP {} refused {}: {}", self.cfg.addr, cmd, reply)),
+        }
+    }
+}
+
+// reply of the command sent, e.g. {"SetConfigName":{"result":"Ok"}} for {"SetConfigName": "..."} or "Reload"
+fn reply_ok(reply: &str, cmd: &str) -> bool {
+    let name: String = cmd.trim_start_matches(['{', '"']).chars().take_while(|c| *c != '"').collect();
+    let compact: String = reply.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.starts_with(&format!("{{\"{}\":{{\"result\":\"Ok\"", name))
+}
+
+impl Drop for CamillaHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// connecting and loading the config for the rate, failing the start like a missing exec
+pub fn start(cfg: &CamillaCfg, rate: usize, dir: &str) -> Result<CamillaHandle> {
+    let stream = connect(&cfg.addr).with_context(|| format!("Cannot connect to CamillaDSP {}", cfg.addr))?;
+    let mut handle = CamillaHandle { stream, cfg: cfg.clone(), stopped: false };
+    handle.set_rate(rate, dir)?;
+    Ok(handle)
+}
+
+fn connect(addr: &str) -> Result<TcpStream> {
+    let sock_addr = addr.to_socket_addrs()?.next().ok_or_else(|| anyhow!("no address of {}", addr))?;
+    let mut stream = TcpStream::connect_timeout(&sock_addr, REPLY_TIMEOUT)?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    stream.set_write_timeout(Some(REPLY_TIMEOUT))?;
+    write!(stream, "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                    Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n", addr, handshake_key())?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    if !status.starts_with("HTTP/1.1 101") {
+        return Err(anyhow!("websocket upgrade refused: {}", status.trim()));
+    }
+    // headers ignored, up to the empty line
+    let mut header = String::new();
+    loop {
+        header.clear();
+        match reader.read_line(&mut header)? {
+            0 => return Err(anyhow!("connection closed within the websocket handshake")),
+            _ if header == "\r\n" || header == "\n" => break,
+            _ => {}
+        }
+    }
+    if !reader.buffer().is_empty() {
+        return Err(anyhow!("unexpected data after the websocket handshake"));
+    }
+    Ok(stream)
+}
+
+// the accept hash is not verified, only a fresh nonce is needed
+fn handshake_key() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+    let seed = nanos ^ ((std::process::id() as u128) << 64);
+    base64(&seed.to_le_bytes())
+}
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let triple = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for idx in 0..4 {
+            match idx <= chunk.len() {
+                true => out.push(CHARS[(triple >> (18 - 6 * idx) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+// client frames are masked, a single unfragmented frame
+fn write_frame<W: Write>(writer: &mut W, opcode: u8, payload: &[u8]) -> Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    let mask = (SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos())).to_be_bytes();
+    frame.extend(mask);
+    frame.extend(payload.iter().enumerate().map(|(idx, byte)| byte ^ mask[idx % 4]));
+    writer.write_all(&frame)?;
+    Ok(())
+}
+
+// opcode and payload, unmasked
+fn read_frame<R: Read>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(anyhow!("websocket frame of {} bytes exceeds max {}", len, MAX_FRAME_LEN));
+    }
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    payload.iter_mut().enumerate().for_each(|(idx, byte)| *byte ^= mask[idx % 4]);
+    Ok((head[0] & 0x0f, payload))
+}
+
+// next text frame, answering pings
+fn read_reply(stream: &mut TcpStream) -> Result<String> {
+    loop {
+        match read_frame(stream)? {
+            (OPCODE_TEXT, payload) => return Ok(String::from_utf8_lossy(&payload).to_string()),
+            (OPCODE_PING, payload) => write_frame(stream, OPCODE_PONG, &payload)?,
+            (OPCODE_CLOSE, _) => return Err(anyhow!("connection closed by CamillaDSP")),
+            (opcode, _) => debug!("CamillaDSP frame with opcode {} ignored", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn reply_of_sent_command() {
+        assert!(reply_ok("{\"Reload\":{\"result\":\"Ok\"}}", "\"Reload\""));
+        assert!(reply_ok("{\"SetConfigName\": {\"result\": \"Ok\"}}", "{\"SetConfigName\": \"/a.yml\"}"));
+        assert!(!reply_ok("{\"SetConfigName\":{\"result\":\"Error\",\"value\":\"Ok\"}}", "{\"SetConfigName\": \"/a.yml\"}"));
+        assert!(!reply_ok("{\"Stop\":{\"result\":\"Ok\"}}", "\"Reload\""));
+    }
+
+    #[test]
+    fn oversized_frame_refused() {
+        let frame = [0x81u8, 127, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(read_frame(&mut &frame[..]).is_err());
+    }
+
+    #[test]
+    fn base64_pads() {
+        assert_eq!(base64(b"gaudio"), "Z2F1ZGlv");
+        assert_eq!(base64(b"ctl"), "Y3Rs");
+        assert_eq!(base64(b"ct"), "Y3Q=");
+        assert_eq!(base64(b"c"), "Yw==");
+    }
+
+    #[test]
+    fn set_config_name_then_reload() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n").unwrap();
+            let mut cmds = Vec::new();
+            for _ in 0..2 {
+                let (opcode, payload) = read_frame(&mut stream).unwrap();
+                assert_eq!(opcode, OPCODE_TEXT);
+                cmds.push(String::from_utf8(payload).unwrap());
+                let reply = match cmds.len() {
+                    1 => b"{\"SetConfigName\":{\"result\":\"Ok\"}}".to_vec(),
+                    _ => b"{\"Reload\": {\"result\": \"Ok\"}}".to_vec(),
+                };
+                stream.write_all(&[0x81, reply.len() as u8]).unwrap();
+                stream.write_all(&reply).unwrap();
+            }
+            cmds
+        });
+        let cfg = CamillaCfg {
+            addr,
+            config: "/etc/camilla/{R}.yml".to_string(),
+            inline: false,
+            rate_token: "{R}".to_string(),
+            rate_scale: "2".parse().unwrap(),
+            dry_run: false,
+        };
+        let mut handle = start(&cfg, 48000, "Test").unwrap();
+        assert_eq!(server.join().unwrap(), vec!["{\"SetConfigName\": \"/etc/camilla/96000.yml\"}", "\"Reload\""]);
+        // server gone, the stop only warns
+        handle.stop();
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Result};
+
+// splitting a command into args with the sh quoting rules: 'single' quotes literal,
+// "double" quotes with \ escaping only $ ` " \ and newline, bare \ escaping any char
+pub fn split(cmd: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    // None = between args, an empty quoted arg "" is still an arg
+    let mut arg: Option<String> = None;
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(anyhow!("unterminated single quote in '{}'", cmd)),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => arg.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(anyhow!("unterminated double quote in '{}'", cmd)),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(anyhow!("unterminated double quote in '{}'", cmd)),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err(anyhow!("trailing backslash in '{}'", cmd)),
+            },
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+// single-quoted for substituting into a sh -c script, ' closing the quote and escaped
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// clap validator of the command params
+pub fn check(cmd: &str) -> Result<()> {
+    split(cmd).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn quoted_args_stay_intact() {
+        assert_eq!(split("alsaloop  -r {R} --opt=\"a b\" '/my dir/x' \"\"").unwrap(),
+                   strings(&["alsaloop", "-r", "{R}", "--opt=a b", "/my dir/x", ""]));
+        assert_eq!(split(r#"echo a\ b "q\"uote\n" 'it''s'"#).unwrap(),
+                   strings(&["echo", "a b", "q\"uote\\n", "its"]));
+        assert!(split("echo 'open").is_err());
+        assert!(split("echo \"open").is_err());
+        assert!(split("echo \\").is_err());
+    }
+
+    #[test]
+    fn quoted_value_stays_one_arg() {
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's; $x"), "'it'\\''s; $x'");
+        assert_eq!(split(&format!("echo {}", quote("it's; $x"))).unwrap(), strings(&["echo", "it's; $x"]));
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Arg, Command};
+use toml::{Table, Value};
+
+// sections of per-direction params, keys without the p/c prefix of the param
+const SECTIONS: [(&str, &str); 2] = [("playback", "p"), ("capture", "c")];
+
+// ids of the built-in clap params and of the config file param itself
+const NON_PARAMS: [&str; 3] = ["config", "help", "version"];
+
+// TOML config converted to param args, keys are the long param names as printed by --print-config
+// keys of the params given on the command line are dropped, also the repeated ones are not merged
+pub fn config_args(path: &str, cmd: &Command, given: &[&str]) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Cannot read config file {}", path))?;
+    let table: Table = content.parse().with_context(|| format!("Cannot parse config file {}", path))?;
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        match (value, SECTIONS.iter().find(|(section, _)| section == key)) {
+            (Value::Table(section), Some((_, prefix))) => {
+                for (key, value) in section {
+                    // e.g. pcmd, p-onstop
+                    let arg = [format!("{}{}", prefix, key), format!("{}-{}", prefix, key)].iter()
+                        .find_map(|id| find_arg(cmd, id))
+                        .ok_or_else(|| {
+                            anyhow!("Unknown key '{}' in section [{}] of config file {}", key, section_name(prefix), path)
+                        })?;
+                    if !given.contains(&arg.get_id()) {
+                        push_arg(&mut args, arg, value)?;
+                    }
+                }
+            }
+            _ => {
+                let arg = find_arg(cmd, key)
+                    .filter(|arg| !NON_PARAMS.contains(&arg.get_id()))
+                    .ok_or_else(|| anyhow!("Unknown key '{}' in config file {}", key, path))?;
+                if !given.contains(&arg.get_id()) {
+                    push_arg(&mut args, arg, value)?;
+                }
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn section_name(prefix: &str) -> &str {
+    SECTIONS.iter().find(|(_, p)| *p == prefix).map_or(prefix, |(section, _)| section)
+}
+
+// snake_case keys accepted too
+fn find_arg<'a, 'help>(cmd: &'a Command<'help>, key: &str) -> Option<&'a Arg<'help>> {
+    let id = key.replace('_', "-");
+    cmd.get_arguments().find(|arg| arg.get_id() == id && arg.get_long().is_some())
+}
+
+fn push_arg(args: &mut Vec<String>, arg: &Arg, value: &Value) -> Result<()> {
+    let long = format!("--{}", arg.get_long().unwrap());
+    if !arg.is_takes_value_set() {
+        let count = match value {
+            Value::Boolean(flag) => *flag as usize,
+            // repeated flags, e.g. verbose = 2
+            Value::Integer(count) if arg.is_multiple_occurrences_set() && *count >= 0 => *count as usize,
+            _ => return Err(anyhow!("Key '{}' of the config file must be a boolean", arg.get_id())),
+        };
+        args.extend(std::iter::repeat_n(long, count));
+        return Ok(());
+    }
+    match value {
+        Value::Array(values) => {
+            let values = values.iter().map(|value| scalar(arg, value)).collect::<Result<Vec<String>>>()?;
+            match arg.is_multiple_occurrences_set() {
+                true => args.extend(values.iter().map(|value| format!("{}={}", long, value))),
+                // comma-delimited values, e.g. soak
+                false => args.push(format!("{}={}", long, values.join(","))),
+            }
+        }
+        value => args.push(format!("{}={}", long, scalar(arg, value)?)),
+    }
+    Ok(())
+}
+
+fn scalar(arg: &Arg, value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Float(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        // e.g. [[watch]] sections, comma-separated KEY=VALUE with cmd last as it may contain commas
+        Value::Table(table) => {
+            let mut pairs = table.iter()
+                .map(|(key, value)| Ok((key.replace('_', "-"), scalar(arg, value)?)))
+                .collect::<Result<Vec<(String, String)>>>()?;
+            pairs.sort_by_key(|(key, _)| key == "cmd");
+            Ok(pairs.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<String>>().join(","))
+        }
+        _ => Err(anyhow!("Key '{}' of the config file must be a string or a number", arg.get_id())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use clap::Arg;
+
+    use super::*;
+
+    #[test]
+    fn keys_and_sections_become_args() {
+        let cmd = Command::new("test")
+            .arg(Arg::new("verbose").long("verbose").multiple_occurrences(true))
+            .arg(Arg::new("pcmd").long("pcmd").takes_value(true).multiple_occurrences(true))
+            .arg(Arg::new("p-onstop").long("p-onstop").takes_value(true))
+            .arg(Arg::new("stop-grace-ms").long("stop-grace-ms").takes_value(true));
+        let path = env::temp_dir().join(format!("gaudio_ctl_config_{}.toml", std::process::id()));
+        fs::write(&path, "verbose = 2\nstop_grace_ms = 100\n[playback]\ncmd = [\"a\", \"b\"]\nonstop = \"x\"\n").unwrap();
+        let args = config_args(path.to_str().unwrap(), &cmd, &[]);
+        let overridden = config_args(path.to_str().unwrap(), &cmd, &["verbose", "pcmd"]);
+        fs::write(&path, "[playback]\nbogus = 1\n").unwrap();
+        let unknown = config_args(path.to_str().unwrap(), &cmd, &[]);
+        fs::write(&path, "help = true\n").unwrap();
+        let non_param = config_args(path.to_str().unwrap(), &cmd.clone().arg(Arg::new("help").long("help")), &[]);
+        fs::remove_file(&path).unwrap();
+        // keys in alphabetical order
+        assert_eq!(args.unwrap(), vec!["--pcmd=a", "--pcmd=b", "--p-onstop=x", "--stop-grace-ms=100", "--verbose",
+                                       "--verbose"]);
+        // given on the command line, replaced as a whole
+        assert_eq!(overridden.unwrap(), vec!["--p-onstop=x", "--stop-grace-ms=100"]);
+        assert!(unknown.is_err());
+        assert!(non_param.is_err());
+    }
+
+    #[test]
+    fn table_array_becomes_repeated_arg() {
+        let cmd = Command::new("test").arg(Arg::new("watch").long("watch").takes_value(true).multiple_occurrences(true));
+        let path = env::temp_dir().join(format!("gaudio_ctl_config_watch_{}.toml", std::process::id()));
+        fs::write(&path, "[[watch]]\nname = \"Rate\"\ncmd = \"a -r {R},b\"\ndevice = 1\n[[watch]]\nname = \"X\"\ncmd = \"c\"\n").unwrap();
+        let args = config_args(path.to_str().unwrap(), &cmd, &[]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(args.unwrap(), vec!["--watch=device=1,name=Rate,cmd=a -r {R},b", "--watch=name=X,cmd=c"]);
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+use crate::Direction;
+
+// holding the first direction's start briefly to start both directions together
+pub struct Coupling {
+    window: Duration,
+    // direction, its latest rate, end of the wait for the other direction
+    pending: Option<(Direction, usize, Instant)>,
+}
+
+impl Coupling {
+    pub fn new(window_ms: usize) -> Self {
+        Coupling { window: Duration::from_millis(window_ms as u64), pending: None }
+    }
+
+    // rates to dispatch now, stops are never held
+    pub fn offer(&mut self, dir: Direction, rate: usize) -> Vec<(Direction, usize)> {
+        match self.pending.take() {
+            Some(pending) if rate == 0 => {
+                if pending.0 != dir {
+                    self.pending = Some(pending);
+                }
+                vec![(dir, 0)]
+            }
+            None if rate == 0 => vec![(dir, 0)],
+            None => {
+                self.pending = Some((dir, rate, Instant::now() + self.window));
+                Vec::new()
+            }
+            // the latest rate of the held direction, still waiting for the other one
+            Some((held, _, deadline)) if held == dir => {
+                self.pending = Some((dir, rate, deadline));
+                Vec::new()
+            }
+            Some((held, held_rate, _)) => vec![(held, held_rate), (dir, rate)],
+        }
+    }
+
+    // time left to wait for the other direction, None = nothing held
+    pub fn remaining(&self) -> Option<Duration> {
+        self.pending.map(|(_, _, deadline)| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    // the held rate once the other direction did not change within the window
+    pub fn expired(&mut self) -> Option<(Direction, usize)> {
+        match self.pending {
+            Some((dir, rate, deadline)) if deadline <= Instant::now() => {
+                self.pending = None;
+                Some((dir, rate))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_direction_releases_both() {
+        let mut coupling = Coupling::new(1000);
+        assert!(coupling.offer(Direction::Capture, 48000).is_empty());
+        assert!(coupling.offer(Direction::Capture, 44100).is_empty());
+        assert_eq!(coupling.offer(Direction::Playback, 44100),
+                   vec![(Direction::Capture, 44100), (Direction::Playback, 44100)]);
+        assert!(coupling.remaining().is_none());
+    }
+
+    #[test]
+    fn stop_passes_and_cancels_held_start() {
+        let mut coupling = Coupling::new(1000);
+        assert!(coupling.offer(Direction::Capture, 48000).is_empty());
+        assert_eq!(coupling.offer(Direction::Capture, 0), vec![(Direction::Capture, 0)]);
+        assert!(coupling.remaining().is_none());
+    }
+
+    #[test]
+    fn held_start_expires_alone() {
+        let mut coupling = Coupling::new(0);
+        assert!(coupling.offer(Direction::Playback, 48000).is_empty());
+        assert_eq!(coupling.expired(), Some((Direction::Playback, 48000)));
+    }
+}
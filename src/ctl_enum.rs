@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+
+// item names of an enumerated ctl, not provided by the alsa crate
+pub fn get_item_names(devname: &str, numid: u32) -> Result<Vec<String>> {
+    let name = CString::new(devname)?;
+    let mut ctl = ptr::null_mut();
+    let mut info = ptr::null_mut();
+    unsafe {
+        if alsa_sys::snd_ctl_open(&mut ctl, name.as_ptr(), 0) < 0 {
+            return Err(anyhow!("Cannot open {} for reading enumerated items", devname));
+        }
+        if alsa_sys::snd_ctl_elem_info_malloc(&mut info) < 0 {
+            alsa_sys::snd_ctl_close(ctl);
+            return Err(anyhow!("Cannot allocate elem info"));
+        }
+        alsa_sys::snd_ctl_elem_info_set_numid(info, numid);
+        let mut names = Vec::new();
+        let mut res = alsa_sys::snd_ctl_elem_info(ctl, info);
+        if res >= 0 {
+            let items = alsa_sys::snd_ctl_elem_info_get_items(info);
+            for item in 0..items {
+                alsa_sys::snd_ctl_elem_info_set_item(info, item);
+                res = alsa_sys::snd_ctl_elem_info(ctl, info);
+                if res < 0 {
+                    break;
+                }
+                let item_name = CStr::from_ptr(alsa_sys::snd_ctl_elem_info_get_item_name(info));
+                names.push(item_name.to_string_lossy().into_owned());
+            }
+        }
+        alsa_sys::snd_ctl_elem_info_free(info);
+        alsa_sys::snd_ctl_close(ctl);
+        if res < 0 {
+            return Err(anyhow!("Cannot read enumerated items of ctl num ID {}, error {}", numid, res));
+        }
+        Ok(names)
+    }
+}
+
+// rates for the enumerated items, by item index
+pub fn get_item_rates(devname: &str, numid: u32, rate_map: &HashMap<String, usize>) -> Result<Vec<usize>> {
+    let rates = get_item_names(devname, numid)?.iter()
+        .map(|name| {
+            let rate = rate_map.get(name).copied().or_else(|| parse_rate(name));
+            if rate.is_none() {
+                warn!("Enumerated item '{}' has no rate, will be treated as rate 0", name);
+            }
+            debug!("Enumerated item '{}' -> rate {:?}", name, rate);
+            rate.unwrap_or(0)
+        })
+        .collect();
+    Ok(rates)
+}
+
+// number with a Hz/kHz unit, or the largest number in the item name, e.g. "48000", "44100 Hz", "44.1kHz", "Mode 2: 96000"
+pub fn parse_rate(name: &str) -> Option<usize> {
+    let mut rest = name;
+    let mut largest = None;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_digit()) {
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let value: Option<f64> = rest[..end].parse().ok();
+        rest = &rest[end..];
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        let unit = rest.trim_start().to_lowercase();
+        // bare k only when not starting a word, e.g. "44.1k" but not "2 kanals"
+        let bare_k = unit.starts_with('k') && !unit[1..].starts_with(|c: char| c.is_alphabetic());
+        if unit.starts_with("khz") || bare_k {
+            return Some((value * 1000.0).round() as usize);
+        }
+        if unit.starts_with("hz") {
+            return Some(value.round() as usize);
+        }
+        largest = largest.max(Some(value.round() as usize));
+    }
+    largest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_number_picked() {
+        assert_eq!(parse_rate("48000"), Some(48000));
+        assert_eq!(parse_rate("44100 Hz"), Some(44100));
+        assert_eq!(parse_rate("44.1kHz"), Some(44100));
+        assert_eq!(parse_rate("96k"), Some(96000));
+        assert_eq!(parse_rate("Mode 2: 96000"), Some(96000));
+        assert_eq!(parse_rate("2ch 48 kHz"), Some(48000));
+        assert_eq!(parse_rate("2 kanals 44100"), Some(44100));
+        assert_eq!(parse_rate("Off"), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::ffi::CString;
+use std::ptr;
+
+use alsa::ctl::{ElemType, ElemValue};
+use alsa::hctl::HCtl;
+use anyhow::{anyhow, Result};
+
+use crate::ctl_enum;
+
+// common sample rates, integer values in this range suggest a rate ctl
+const MIN_RATE: usize = 8000;
+const MAX_RATE: usize = 768000;
+
+// the most common rate, a rate ctl must allow it
+const COMMON_RATE: i64 = 48000;
+
+// all ctl elements of the card with their current values, rate-like ones marked with '*', unreadable ones listed too
+pub fn print_controls(devname: &str) -> Result<()> {
+    let h = HCtl::new(devname, false)?;
+    h.load()?;
+    println!("  {:<6} {:>5}  {:<40} {:>3} {:>3} {:>3}  {:<10} VALUE", "IFACE", "NUMID", "NAME", "DEV", "SUB", "IDX", "TYPE");
+    for elem in h.elem_iter() {
+        let id = elem.get_id()?;
+        let info = elem.info()?;
+        let elem_type = info.get_type();
+        let name = id.get_name()?.to_string();
+        let values = match elem_type {
+            ElemType::Enumerated => elem.read().map_err(Into::into)
+                .and_then(|value| enum_values(devname, id.get_numid(), &value, info.get_count())),
+            _ => elem.read().map(|value| read_values(&value, elem_type, info.get_count())).map_err(Into::into),
+        };
+        let range = match elem_type {
+            ElemType::Integer => integer_range(devname, id.get_numid()).ok(),
+            _ => None,
+        };
+        let (mark, values) = match values {
+            Ok(values) if looks_like_rate(&name, elem_type, &values, range) => ('*', values.join(",")),
+            Ok(values) => (' ', values.join(",")),
+            Err(err) => (' ', format!("<unreadable: {}>", err)),
+        };
+        println!("{} {:<6} {:>5}  {:<40} {:>3} {:>3} {:>3}  {:<10} {}", mark, format!("{:?}", id.get_interface()),
+                 id.get_numid(), name, id.get_device(), id.get_subdevice(), id.get_index(), format!("{:?}", elem_type),
+                 values);
+    }
+    Ok(())
+}
+
+fn read_values(value: &ElemValue, elem_type: ElemType, count: u32) -> Vec<String> {
+    (0..count).filter_map(|idx| match elem_type {
+        ElemType::Boolean => value.get_boolean(idx).map(|v| v.to_string()),
+        ElemType::Integer => value.get_integer(idx).map(|v| v.to_string()),
+        ElemType::Integer64 => value.get_integer64(idx).map(|v| v.to_string()),
+        ElemType::Bytes => value.get_byte(idx).map(|v| format!("{:#04x}", v)),
+        _ => None,
+    }).collect()
+}
+
+// selected item names
+fn enum_values(devname: &str, numid: u32, value: &ElemValue, count: u32) -> Result<Vec<String>> {
+    let items = ctl_enum::get_item_names(devname, numid)?;
+    Ok((0..count)
+        .filter_map(|idx| value.get_enumerated(idx))
+        .map(|item| items.get(item as usize).cloned().unwrap_or_else(|| item.to_string()))
+        .collect())
+}
+
+// min and max of an integer ctl, not provided by the alsa crate
+fn integer_range(devname: &str, numid: u32) -> Result<(i64, i64)> {
+    let name = CString::new(devname)?;
+    let mut ctl = ptr::null_mut();
+    let mut info = ptr::null_mut();
+    unsafe {
+        if alsa_sys::snd_ctl_open(&mut ctl, name.as_ptr(), 0) < 0 {
+            return Err(anyhow!("Cannot open {} for reading the ctl range", devname));
+        }
+        if alsa_sys::snd_ctl_elem_info_malloc(&mut info) < 0 {
+            alsa_sys::snd_ctl_close(ctl);
+            return Err(anyhow!("Cannot allocate elem info"));
+        }
+        alsa_sys::snd_ctl_elem_info_set_numid(info, numid);
+        let res = alsa_sys::snd_ctl_elem_info(ctl, info);
+        let range = (alsa_sys::snd_ctl_elem_info_get_min(info) as i64, alsa_sys::snd_ctl_elem_info_get_max(info) as i64);
+        alsa_sys::snd_ctl_elem_info_free(info);
+        alsa_sys::snd_ctl_close(ctl);
+        if res < 0 {
+            return Err(anyhow!("Cannot read range of ctl num ID {}, error {}", numid, res));
+        }
+        Ok(range)
+    }
+}
+
+// by the name, or by the current values being sample rates within a range allowing the rates
+fn looks_like_rate(name: &str, elem_type: ElemType, values: &[String], range: Option<(i64, i64)>) -> bool {
+    if name.to_lowercase().contains("rate") {
+        return true;
+    }
+    let is_rate = |rate: usize| (MIN_RATE..=MAX_RATE).contains(&rate);
+    match elem_type {
+        ElemType::Integer => range.is_some_and(|(min, max)| (0..=MIN_RATE as i64).contains(&min) && max >= COMMON_RATE)
+            && values.iter().filter_map(|value| value.parse().ok()).any(is_rate),
+        ElemType::Enumerated => !values.is_empty() && values.iter().all(|value| ctl_enum::parse_rate(value).is_some_and(is_rate)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_by_name_or_value() {
+        assert!(looks_like_rate("Capture Rate", ElemType::Integer, &["0".to_string()], None));
+        assert!(looks_like_rate("Sample Freq", ElemType::Integer, &["48000".to_string()], Some((0, 1000000))));
+        assert!(looks_like_rate("Clock Source", ElemType::Enumerated, &["44.1kHz".to_string()], None));
+        assert!(!looks_like_rate("Master Volume", ElemType::Integer, &["100".to_string()], Some((0, 100))));
+        assert!(!looks_like_rate("Position", ElemType::Integer, &["48000".to_string()], Some((-100000, 100000))));
+        assert!(!looks_like_rate("Offset", ElemType::Integer, &["48000".to_string()], None));
+        assert!(!looks_like_rate("Capture Switch", ElemType::Boolean, &["true".to_string()], None));
+        assert!(!looks_like_rate("Input Source", ElemType::Enumerated, &["Input 1".to_string()], None));
+    }
+}
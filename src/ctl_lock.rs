@@ -0,0 +1,85 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use anyhow::Result;
+use log::{debug, warn};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::libc;
+use nix::unistd::geteuid;
+
+use crate::error::Error;
+
+// advisory lock of a rate ctl, released when dropped or on exit
+pub struct CtlLock {
+    _file: File,
+}
+
+// another instance holding the lock of the same card and num ID refuses the start
+pub fn lock_ctl(dir: &str, card: &str, numid: u32, name: &str) -> Result<Option<CtlLock>> {
+    // card names may contain chars unusable in a file name
+    let card: String = card.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    let path = Path::new(dir).join(format!("gaudio_ctl-{}-{}.lock", card, numid));
+    // a symlink planted in a shared dir must not redirect the PID write
+    let mut file = match OpenOptions::new().read(true).write(true).create(true).truncate(false)
+        .custom_flags(libc::O_NOFOLLOW).open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("Cannot create lock file {}, not locking ctl '{}', error: {}", path.display(), name, err);
+            return Ok(None);
+        }
+    };
+    let meta = file.metadata()?;
+    if !meta.is_file() || meta.uid() != geteuid().as_raw() {
+        warn!("Lock file {} is not a regular file owned by UID {}, not locking ctl '{}'", path.display(), geteuid(), name);
+        return Ok(None);
+    }
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => {}
+        Err(Errno::EWOULDBLOCK) => {
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            return Err(Error::CtlLocked {
+                name: name.to_string(),
+                numid,
+                path: path.display().to_string(),
+                holder: holder.trim().to_string(),
+            }.into());
+        }
+        Err(err) => {
+            warn!("Cannot lock {}, not locking ctl '{}', error: {}", path.display(), name, err);
+            return Ok(None);
+        }
+    }
+    // holder PID for the message of the refused instance
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    debug!("Locked ctl '{}' (num ID {}) with {}", name, numid, path.display());
+    Ok(Some(CtlLock { _file: file }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn symlinked_lock_file_not_followed() {
+        let dir = std::env::temp_dir().join(format!("gaudio_ctl_lock_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target");
+        fs::write(&target, "keep").unwrap();
+        symlink(&target, dir.join("gaudio_ctl-hw_9-3.lock")).unwrap();
+        let symlinked = lock_ctl(dir.to_str().unwrap(), "hw:9", 3, "Test").unwrap();
+        let locked = lock_ctl(dir.to_str().unwrap(), "hw:9", 4, "Test").unwrap();
+        let content = fs::read_to_string(&target).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(symlinked.is_none());
+        assert!(locked.is_some());
+        assert_eq!(content, "keep");
+    }
+}
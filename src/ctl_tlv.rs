@@ -0,0 +1,39 @@
+use std::ffi::CString;
+use std::ptr;
+
+use anyhow::{anyhow, Result};
+
+// max TLV size read, in 32-bit words
+const MAX_TLV_WORDS: usize = 256;
+
+// TLV words (type, length in bytes, data) of a ctl, not provided by the alsa crate
+pub fn read_tlv(devname: &str, numid: u32) -> Result<Vec<u32>> {
+    let name = CString::new(devname)?;
+    let mut ctl = ptr::null_mut();
+    let mut id = ptr::null_mut();
+    let mut tlv = vec![0u32; MAX_TLV_WORDS];
+    unsafe {
+        if alsa_sys::snd_ctl_open(&mut ctl, name.as_ptr(), 0) < 0 {
+            return Err(anyhow!("Cannot open {} for reading TLV", devname));
+        }
+        if alsa_sys::snd_ctl_elem_id_malloc(&mut id) < 0 {
+            alsa_sys::snd_ctl_close(ctl);
+            return Err(anyhow!("Cannot allocate elem id"));
+        }
+        alsa_sys::snd_ctl_elem_id_set_numid(id, numid);
+        let res = alsa_sys::snd_ctl_elem_tlv_read(ctl, id, tlv.as_mut_ptr(), (MAX_TLV_WORDS * 4) as u32);
+        alsa_sys::snd_ctl_elem_id_free(id);
+        alsa_sys::snd_ctl_close(ctl);
+        if res < 0 {
+            return Err(anyhow!("Cannot read TLV of ctl num ID {}, error {}", numid, res));
+        }
+    }
+    let words = (2 + tlv[1] as usize / 4).min(MAX_TLV_WORDS);
+    tlv.truncate(words);
+    Ok(tlv)
+}
+
+// comma separated hex words, also for the {TLV} placeholder
+pub fn format_tlv(tlv: &[u32]) -> String {
+    tlv.iter().map(|word| format!("{:#010x}", word)).collect::<Vec<String>>().join(",")
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::thread;
+
+use anyhow::Result;
+use log::{debug, warn};
+use zbus::blocking::{Connection, ConnectionBuilder};
+use zbus::zvariant::Value;
+use zbus::{fdo, interface, SignalContext};
+
+use crate::executor::ExecState;
+use crate::{DirHandle, Msg};
+
+const BUS_NAME: &str = "org.gaudio_ctl";
+const IFACE_NAME: &str = "org.gaudio_ctl.Direction";
+
+// one object per direction, e.g. /org/gaudio_ctl/Playback
+struct DirIface {
+    handle: DirHandle,
+}
+
+impl DirIface {
+    fn send(&self, msg: Msg) -> fdo::Result<()> {
+        self.handle.sender.send(msg).map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+}
+
+#[interface(name = "org.gaudio_ctl.Direction")]
+impl DirIface {
+    #[zbus(property)]
+    fn rate(&self) -> u32 {
+        match self.handle.state.get() {
+            ExecState::Running(rate) => rate as u32,
+            _ => 0,
+        }
+    }
+
+    #[zbus(property)]
+    fn running(&self) -> bool {
+        matches!(self.handle.state.get(), ExecState::Running(_))
+    }
+
+    #[zbus(property)]
+    fn state(&self) -> String {
+        self.handle.state.get().to_string()
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        debug!("{}: D-Bus stop", self.handle.dir);
+        self.send(Msg::StopExec)
+    }
+
+    fn restart(&self) -> fdo::Result<()> {
+        debug!("{}: D-Bus restart", self.handle.dir);
+        self.send(Msg::Restart)
+    }
+
+    // declared for introspection, emitted by the watcher thread
+    #[zbus(signal)]
+    async fn transition(ctxt: &SignalContext<'_>, state: &str) -> zbus::Result<()>;
+}
+
+pub fn start_dbus_thread(bus: &str, dirs: Vec<DirHandle>) -> Result<()> {
+    let mut builder = match bus {
+        "system" => ConnectionBuilder::system()?,
+        _ => ConnectionBuilder::session()?,
+    };
+    for handle in &dirs {
+        builder = builder.serve_at(obj_path(&handle.dir), DirIface { handle: handle.clone() })?;
+    }
+    let conn = builder.name(BUS_NAME)?.build()?;
+    debug!("Registered on D-Bus {} bus as {}", bus, BUS_NAME);
+    for handle in dirs {
+        let conn = conn.clone();
+        let recv = handle.state.watch();
+        thread::Builder::new()
+            .name(format!("{} D-Bus Thread", handle.dir))
+            .spawn(move || {
+                for state in recv.iter() {
+                    if let Err(err) = emit_state_changed(&conn, &handle.dir, state) {
+                        warn!("{}: D-Bus signal failed, error: {}", handle.dir, err);
+                    }
+                }
+            })?;
+    }
+    Ok(())
+}
+
+fn emit_state_changed(conn: &Connection, dir: &str, state: ExecState) -> zbus::Result<()> {
+    let path = obj_path(dir);
+    conn.emit_signal(None::<&str>, path.as_str(), IFACE_NAME, "Transition", &(state.to_string(),))?;
+    // all properties derive from the state
+    let changed: HashMap<&str, Value> = HashMap::new();
+    conn.emit_signal(None::<&str>, path.as_str(), "org.freedesktop.DBus.Properties", "PropertiesChanged",
+                     &(IFACE_NAME, changed, vec!["Rate", "Running", "State"]))
+}
+
+// desktop notifications on starts/stops via the freedesktop notification spec
+pub fn start_notify_thread(dirs: Vec<DirHandle>) -> Result<()> {
+    let conn = Connection::session()?;
+    for handle in dirs {
+        let conn = conn.clone();
+        let recv = handle.state.watch();
+        thread::Builder::new()
+            .name(format!("{} Notify Thread", handle.dir))
+            .spawn(move || {
+                for state in recv.iter() {
+                    let body = match state {
+                        ExecState::Running(rate) => format!("{} started at {} Hz", handle.dir, rate),
+                        ExecState::Stopped => format!("{} stopped", handle.dir),
+                        ExecState::Failed => format!("{} failed, not restarting", handle.dir),
+                        _ => continue,
+                    };
+                    // no notification daemon is no problem
+                    if let Err(err) = notify(&conn, &body) {
+                        debug!("{}: Notification failed, error: {}", handle.dir, err);
+                    }
+                }
+            })?;
+    }
+    Ok(())
+}
+
+fn notify(conn: &Connection, body: &str) -> zbus::Result<()> {
+    let hints: HashMap<&str, Value> = HashMap::new();
+    conn.call_method(Some("org.freedesktop.Notifications"), "/org/freedesktop/Notifications",
+                     Some("org.freedesktop.Notifications"), "Notify",
+                     &("gaudio_ctl", 0u32, "audio-card", "USB Audio Gadget", body, Vec::<&str>::new(), hints, -1i32))?;
+    Ok(())
+}
+
+fn obj_path(dir: &str) -> String {
+    format!("/org/gaudio_ctl/{}", dir)
+}
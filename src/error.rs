@@ -0,0 +1,40 @@
+use std::io;
+
+use thiserror::Error;
+
+// failures callers may want to handle specifically, the rest stays anyhow
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Cannot open card {devname}")]
+    CardUnavailable {
+        devname: String,
+        #[source]
+        source: alsa::Error,
+    },
+    #[error("Card '{name}' not found by index, ID, name or longname, available cards: {available}")]
+    CardNotFound { name: String, available: String },
+    #[error("Neither capture ctl '{cctl}' nor playback ctl '{pctl}' found")]
+    CtlNotFound { cctl: String, pctl: String },
+    #[error("Cannot subscribe for ctl events of {devname}{hint}, polling with --poll-interval-ms may work instead")]
+    SubscribeFailed {
+        devname: String,
+        // permission diagnostic, empty if not relevant
+        hint: String,
+        #[source]
+        source: alsa::Error,
+    },
+    #[error("Ctl '{name}' is of type {elem_type}, not an integer, boolean or enumerated rate control")]
+    UnsupportedCtlType { name: String, elem_type: String },
+    #[error("Boolean ctl '{0}' requires a fixed rate to run at")]
+    MissingFixedRate(String),
+    #[error("Ctl '{name}' (num ID {numid}) is already handled by another instance (PID {holder}, lock {path})")]
+    CtlLocked { name: String, numid: u32, path: String, holder: String },
+    #[error("Executable {0} not found")]
+    ExecNotFound(String),
+    #[error("Cannot spawn {exec}")]
+    Spawn {
+        exec: String,
+        #[source]
+        source: io::Error,
+    },
+}
@@ -0,0 +1,72 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+
+// one line per ctl event: timestamp_ms numid index mask dir|- rate|-
+pub struct EventTracer {
+    file: File,
+}
+
+impl EventTracer {
+    pub fn new(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Cannot open event trace file {}", path))?;
+        Ok(EventTracer { file })
+    }
+
+    pub fn record(&mut self, numid: u32, index: u32, mask: u32, dir: Option<&str>, rate: Option<usize>) -> Result<()> {
+        let ts_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let rate = rate.map_or("-".to_string(), |r| r.to_string());
+        writeln!(self.file, "{} {} {} {} {} {}", ts_ms, numid, index, mask, dir.unwrap_or("-"), rate)?;
+        Ok(())
+    }
+}
+
+pub struct TracedEvent {
+    pub ts_ms: u64,
+    pub numid: u32,
+    pub index: u32,
+    pub mask: u32,
+    // None for events of other ctls
+    pub dir: Option<String>,
+    // None when the value was not read
+    pub rate: Option<usize>,
+}
+
+pub fn read_trace(path: &str) -> Result<Vec<TracedEvent>> {
+    let file = File::open(path).with_context(|| format!("Cannot open event trace file {}", path))?;
+    let mut events = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = parse_line(&line).with_context(|| format!("Invalid event trace line {}: '{}'", i + 1, line))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn parse_line(line: &str) -> Result<TracedEvent> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 6 {
+        return Err(anyhow!("expected 6 fields, found {}", fields.len()));
+    }
+    Ok(TracedEvent {
+        ts_ms: fields[0].parse()?,
+        numid: fields[1].parse()?,
+        index: fields[2].parse()?,
+        mask: fields[3].parse()?,
+        dir: optional(fields[4]).map(str::to_string),
+        rate: optional(fields[5]).map(str::parse).transpose()?,
+    })
+}
+
+fn optional(field: &str) -> Option<&str> {
+    match field {
+        "-" => None,
+        _ => Some(field),
+    }
+}
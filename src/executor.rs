@@ -1,75 +1,754 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
 use std::io;
-use std::process::{Child, Command};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::Result;
-use cancellable_timer::Timer;
-use crossbeam_channel::Receiver;
-use log::{debug, error, trace, warn};
+use anyhow::{anyhow, Result};
+use clap::ArgEnum;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use log::{debug, error, info, Level, log, trace, warn};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, killpg, Signal};
+use nix::unistd::Pid;
 
+use crate::backoff::Backoff;
+use crate::builtin_loop;
+use crate::builtin_loop::{LoopCfg, LoopHandle};
+use crate::camilla;
+use crate::camilla::{CamillaCfg, CamillaHandle};
+use crate::cmdline;
+use crate::error::Error;
+use crate::json;
 use crate::Msg;
+use crate::takeover;
+
+// how often the gate file is checked
+const GATE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// how often the executables of running commands are checked for changes
+const BINARY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// how often the running commands are checked for exiting on their own
+const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+// how often the quitting exec thread is checked for finishing
+const QUIT_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// how often the started commands are checked within the confirm time
+const CONFIRM_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+// placeholder replaced with the rate alias, or the rate when unmapped
+const RATE_ALIAS_TOKEN: &str = "{RALIAS}";
+
+// placeholder in stop commands replaced with the rate which was running
+const PREV_RATE_TOKEN: &str = "{PREV_R}";
+
+// placeholders replaced with the card name and the ctl coordinates of the direction
+const CARD_TOKEN: &str = "{CARD}";
+const DEVICE_TOKEN: &str = "{DEVICE}";
+const SUBDEVICE_TOKEN: &str = "{SUBDEVICE}";
+
+// placeholder in error commands replaced with the error or exit status
+const ERROR_TOKEN: &str = "{ERR}";
+
+// placeholders in TLV commands replaced with the direction and the TLV words
+const DIR_TOKEN: &str = "{DIR}";
+const TLV_TOKEN: &str = "{TLV}";
+
+// current state of the direction, shared with the main thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecState {
+    #[default]
+    Stopped,
+    // waiting for the debouncing timeout before start
+    Debouncing,
+    // started with rate, not confirmed alive yet
+    Starting(usize),
+    // exec running with rate
+    Running(usize),
+    // max restarts of the exited exec reached, stopped until the next rate change
+    Failed,
+}
+
+impl fmt::Display for ExecState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecState::Stopped => write!(f, "Stopped"),
+            ExecState::Debouncing => write!(f, "Debouncing"),
+            ExecState::Starting(rate) => write!(f, "Starting({})", rate),
+            ExecState::Running(rate) => write!(f, "Running({})", rate),
+            ExecState::Failed => write!(f, "Failed"),
+        }
+    }
+}
+
+// upper bounds of the start latency buckets, the last bucket is unbounded
+const LATENCY_BUCKETS_MS: [u64; 8] = [10, 50, 100, 250, 500, 1000, 2500, 5000];
+
+// start latencies counted in fixed buckets, readable without locking
+#[derive(Default)]
+pub struct LatencyHistogram {
+    counts: [AtomicUsize; LATENCY_BUCKETS_MS.len() + 1],
+    sum_ms: AtomicUsize,
+}
+
+impl LatencyHistogram {
+    fn record(&self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|&bound| ms <= bound).unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms as usize, Ordering::Relaxed);
+    }
+
+    // of all recorded latencies
+    pub fn sum_ms(&self) -> usize {
+        self.sum_ms.load(Ordering::Relaxed)
+    }
+
+    // upper bound in ms (None = unbounded) with the count of each bucket
+    pub fn buckets(&self) -> Vec<(Option<u64>, usize)> {
+        self.counts.iter()
+            .enumerate()
+            .map(|(idx, count)| (LATENCY_BUCKETS_MS.get(idx).copied(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn format(&self) -> String {
+        self.buckets().iter()
+            .map(|(bound, count)| match bound {
+                Some(bound) => format!("<={}ms: {}", bound, count),
+                None => format!(">{}ms: {}", LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1], count),
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+// lifetime counts of the exec thread events, readable without locking
+#[derive(Default)]
+pub struct Counters {
+    pub starts: AtomicUsize,
+    pub stops: AtomicUsize,
+    pub restarts: AtomicUsize,
+    // failed spawns and execs died within the confirm time
+    pub spawn_failures: AtomicUsize,
+    // debounced starts ended with a stop
+    pub debounce_cancels: AtomicUsize,
+    // rate changes handled by the reload signal/command instead of a restart
+    pub reloads: AtomicUsize,
+    // exited execs not restarted anymore, max restarts reached
+    pub exit_giveups: AtomicUsize,
+}
+
+impl Counters {
+    fn inc(counter: &AtomicUsize) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// exec state shared with the main thread and the control interfaces
+#[derive(Default)]
+pub struct SharedState {
+    state: Mutex<ExecState>,
+    // notified on every state change
+    watchers: Mutex<Vec<Sender<ExecState>>>,
+    // end of the pending debouncing
+    debounce_deadline: Mutex<Option<Instant>>,
+    // since when the exec thread handles a message, None = waiting for one
+    busy_since: Mutex<Option<Instant>>,
+    // from receiving the rate to spawning
+    pub latency: LatencyHistogram,
+    pub counters: Counters,
+    // of the running exec processes
+    pids: Mutex<Vec<u32>>,
+}
+
+impl SharedState {
+    pub fn get(&self) -> ExecState {
+        *self.state.lock().unwrap()
+    }
+
+    // returns the previous state if changed
+    fn set(&self, new_state: ExecState) -> Option<ExecState> {
+        let mut state = self.state.lock().unwrap();
+        if *state == new_state {
+            return None;
+        }
+        let old_state = *state;
+        *state = new_state;
+        // dropping watchers with closed receivers
+        self.watchers.lock().unwrap().retain(|w| w.send(new_state).is_ok());
+        Some(old_state)
+    }
+
+    pub fn debounce_remaining(&self) -> Option<Duration> {
+        self.debounce_deadline.lock().unwrap().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn set_debounce_deadline(&self, deadline: Option<Instant>) {
+        *self.debounce_deadline.lock().unwrap() = deadline;
+    }
+
+    // how long the exec thread handles the current message
+    pub fn busy_for(&self) -> Option<Duration> {
+        self.busy_since.lock().unwrap().map(|since| since.elapsed())
+    }
+
+    fn set_busy(&self, busy: bool) {
+        *self.busy_since.lock().unwrap() = busy.then(Instant::now);
+    }
+
+    pub fn pids(&self) -> Vec<u32> {
+        self.pids.lock().unwrap().clone()
+    }
+
+    // returns false if unchanged
+    fn set_pids(&self, pids: Vec<u32>) -> bool {
+        let mut current = self.pids.lock().unwrap();
+        if *current == pids {
+            return false;
+        }
+        *current = pids;
+        true
+    }
+
+    pub fn watch(&self) -> Receiver<ExecState> {
+        let (sender, recv) = unbounded();
+        self.watchers.lock().unwrap().push(sender);
+        recv
+    }
+}
+
+// started exec process
+pub enum ExecChild {
+    Spawned(Child),
+    // dry run, nothing spawned
+    Simulated,
+    // left running by the previous controller instance
+    Adopted(u32),
+    // in-process loop instead of the commands
+    Builtin(LoopHandle),
+    // connection to CamillaDSP instead of the commands
+    Camilla(CamillaHandle),
+}
 
 pub struct ExecData {
     dir: String,
-    // running exec process
-    child: Option<Child>,
-    // debouncing timer
-    timer: Timer,
-    // debouncing timeout (0 = no debouncing)
-    timeout: usize,
-    // is currently in debouncing wait
-    debouncing_now: Arc<AtomicBool>,
+    // running exec processes, started and killed as a group
+    children: Vec<ExecChild>,
+    // when the running exec was started
+    started_at: Option<Instant>,
+    cfg: ExecCfg,
     // value reported by the Rate ctl
     rate: usize,
     // to receive new rate
     recv: Receiver<Msg>,
+    // current state, read by the main thread
+    state: Arc<SharedState>,
+    stats: ExecStats,
+    hooks: Hooks,
+    // start deferred by the closed gate
+    gated: bool,
+    // copying PCM in-process instead of running the commands
+    builtin: Option<LoopCfg>,
+    // executables of the running commands with their mtimes, for the binary watch
+    binaries: Vec<(PathBuf, Option<SystemTime>)>,
+    // running idle command
+    idle: Option<ExecChild>,
+    // reload received within the debouncing/stop grace, applied afterwards
+    pending_reload: Option<ExecUpdate>,
+    // consecutive restarts of exited execs, reset by a rate change
+    exit_restarts: usize,
+    // delays between the restarts of exited execs, reset by a rate change
+    exit_backoff: Option<Backoff>,
+    // pending restart of the exited exec
+    restart_at: Option<Instant>,
+    // message which ended the confirm wait, handled next
+    held: Option<Msg>,
+}
+
+// counters accumulated over the whole run, reported on quit
+#[derive(Debug, Default)]
+struct ExecStats {
+    starts: usize,
+    stops: usize,
+    restarts: usize,
+    reloads: usize,
+    // stops cancelled by resuming at the running rate
+    resumes: usize,
+    // total run time of the children
+    runtime: Duration,
+    // sum of start latencies from receiving the rate to spawning
+    latency: Duration,
+}
+
+impl ExecStats {
+    fn log_summary(&self, dir: &str) {
+        let avg_latency = match self.starts {
+            0 => 0,
+            starts => self.latency.as_millis() / starts as u128,
+        };
+        info!("{}: Summary: {} starts, {} stops, {} restarts, {} reloads, {} resumes, total runtime {} ms, average start latency {} ms",
+              dir, self.starts, self.stops, self.restarts, self.reloads, self.resumes, self.runtime.as_millis(), avg_latency);
+    }
 }
 
 impl ExecData {
-    pub fn new(dir: &str, timer: Timer, timeout: usize, debouncing: Arc<AtomicBool>, recv: Receiver<Msg>) -> Self {
+    pub fn new(dir: &str, cfg: ExecCfg, recv: Receiver<Msg>, state: Arc<SharedState>, hooks: Hooks,
+               builtin: Option<LoopCfg>) -> Self {
         ExecData {
             dir: dir.to_string(),
-            child: None,
+            children: Vec::new(),
+            started_at: None,
             rate: 0,
-            timer,
-            timeout,
-            debouncing_now: debouncing,
+            cfg,
             recv,
+            state,
+            stats: ExecStats::default(),
+            hooks,
+            gated: false,
+            builtin,
+            binaries: Vec::new(),
+            idle: None,
+            pending_reload: None,
+            exit_restarts: 0,
+            exit_backoff: None,
+            restart_at: None,
+            held: None,
+        }
+    }
+}
+
+// reloaded config of the exec thread
+pub struct ExecUpdate {
+    pub cfg: ExecCfg,
+    pub cmds: Vec<CmdCfg>,
+    pub hooks: Hooks,
+}
+
+// commands run on transitions
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    // run synchronously after stopping the exec
+    pub onstop: Option<CmdCfg>,
+    // run synchronously after a failed start or an exec death
+    pub onerror: Option<CmdCfg>,
+    // long-running while stopped at rate 0, killed by the next start
+    pub idle: Option<CmdCfg>,
+    // run synchronously on a rate change of the running exec instead of restarting it
+    pub reload: Option<CmdCfg>,
+    // run synchronously on a TLV change of the rate ctl
+    pub tlv: Option<CmdCfg>,
+}
+
+// timing params of the exec thread
+#[derive(Debug, Clone)]
+pub struct ExecCfg {
+    // debouncing timeout in ms (0 = no debouncing)
+    pub timeout: usize,
+    // delay of stop in ms (0 = stop immediately)
+    pub stop_grace: usize,
+    // delay of stop in ms for resuming at the same rate (0 = no keepalive)
+    pub keepalive: usize,
+    // report start latency
+    pub show_timing: bool,
+    // max run time of the child in ms (0 = unlimited)
+    pub max_runtime: usize,
+    // max run time of hook commands in ms (0 = unlimited)
+    pub hook_timeout: usize,
+    // dir of state files for keeping the execs running across restarts
+    pub takeover_dir: Option<String>,
+    // execs run only while the file exists
+    pub gate_file: Option<String>,
+    // started execs must be alive after this time in ms to count as running (0 = no check)
+    pub confirm: usize,
+    pub debounce_mode: DebounceMode,
+    // stability time in ms of the stable debouncing
+    pub stable: usize,
+    // max wait in ms of the stable debouncing for an oscillating rate
+    pub stable_max: usize,
+    // max difference in Hz of a new rate still treated as the running one
+    pub rate_tolerance: usize,
+    // restart the running execs when their executable changes
+    pub watch_binary: bool,
+    // restart the execs exited on their own
+    pub restart_on_exit: bool,
+    // max consecutive restarts of exited execs (0 = unlimited)
+    pub max_restarts: usize,
+    // delay in ms before restarting an exited exec, doubled with each restart in a row up to the max
+    pub backoff_base: usize,
+    pub backoff_max: usize,
+    // file with the PIDs of the running execs, per direction
+    pub pid_file: Option<String>,
+    // sent to stop the execs, SIGKILL after kill_timeout ms (0 = SIGKILL right away)
+    pub stop_signal: Signal,
+    pub kill_timeout: usize,
+    // sent to the running execs on a rate change instead of restarting them
+    pub reload_signal: Option<Signal>,
+    // CamillaDSP switched to the config of the rate instead of running the commands
+    pub camilla: Option<CamillaCfg>,
+    // printing each handled rate as JSON on stdout
+    pub json_events: bool,
+}
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebounceMode {
+    // fixed window after the first rate change
+    Fixed,
+    // window restarted by every rate change, up to a max wait
+    Stable,
+}
+
+impl ExecCfg {
+    // 0 = no debouncing
+    fn debounce_window(&self) -> usize {
+        match self.debounce_mode {
+            DebounceMode::Fixed => self.timeout,
+            DebounceMode::Stable => self.stable,
+        }
+    }
+
+    // longest delay of stop in ms, a different rate restarts also within the keepalive
+    pub fn stop_window(&self) -> usize {
+        self.stop_grace.max(self.keepalive)
+    }
+
+    // longest quit in ms: a running hook (5s when unlimited), killing the exec and the idle command, with a margin
+    pub fn quit_window(&self) -> usize {
+        let hook_timeout = if self.hook_timeout > 0 { self.hook_timeout } else { 5000 };
+        hook_timeout + 2 * self.kill_timeout + 1000
+    }
+
+    // longest handling of a single message in ms: debouncing, stop delay, the onstop, reload and onerror hooks,
+    // killing the exec and the idle command and the confirm wait, None with unlimited hooks
+    pub fn busy_window(&self) -> Option<usize> {
+        match self.hook_timeout {
+            0 => None,
+            hook_timeout => Some(self.max_debounce() + self.stop_window() + 3 * hook_timeout + 2 * self.kill_timeout + self.confirm),
+        }
+    }
+
+    // longest possible debouncing in ms
+    pub fn max_debounce(&self) -> usize {
+        match self.debounce_mode {
+            DebounceMode::Fixed => self.timeout,
+            DebounceMode::Stable => self.stable.max(self.stable_max),
+        }
+    }
+}
+
+// handling of a child output stream
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildOutput {
+    // to the controller stdout/stderr
+    Inherit,
+    // line by line to the controller log
+    Log,
+    // dropped
+    Null,
+}
+
+// params common to all commands
+#[derive(Debug, Clone)]
+pub struct SpawnCfg {
+    // placeholder replaced with the rate
+    pub rate_token: String,
+    // env variable with the rate
+    pub rate_env: Option<String>,
+    // rate -> string for the {RALIAS} placeholder
+    pub rate_aliases: HashMap<usize, String>,
+    pub stdout: ChildOutput,
+    pub stderr: ChildOutput,
+    // level of the logged output lines
+    pub log_level: Level,
+    // only log the commands
+    pub dry_run: bool,
+    // logged stderr lines with warning priority
+    pub journald: bool,
+    // multiplier of the rate passed to the commands
+    pub rate_scale: RateScale,
+    // gadget card name
+    pub card: String,
+    // coordinates of the rate ctl of the direction
+    pub device: u32,
+    pub subdevice: u32,
+}
+
+// rational multiplier NUM/DEN of the rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateScale {
+    num: usize,
+    den: usize,
+}
+
+impl Default for RateScale {
+    fn default() -> Self {
+        RateScale { num: 1, den: 1 }
+    }
+}
+
+impl RateScale {
+    // None when the scaled rate is not an integer
+    pub fn apply(&self, rate: usize) -> Option<usize> {
+        let scaled = rate * self.num;
+        scaled.is_multiple_of(self.den).then(|| scaled / self.den)
+    }
+}
+
+impl fmt::Display for RateScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.den {
+            1 => write!(f, "{}", self.num),
+            den => write!(f, "{}/{}", self.num, den),
+        }
+    }
+}
+
+// integer N or ratio NUM/DEN
+impl FromStr for RateScale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+        let scale = RateScale { num: num.trim().parse()?, den: den.trim().parse()? };
+        if scale.num == 0 || scale.den == 0 {
+            return Err(anyhow!("scale '{}' must be nonzero", s));
         }
+        Ok(scale)
+    }
+}
+
+impl SpawnCfg {
+    // placeholders with their values for the rate
+    fn placeholders(&self, rate: usize) -> Vec<(&str, String)> {
+        let alias = self.rate_aliases.get(&rate).cloned().unwrap_or_else(|| rate.to_string());
+        vec![(self.rate_token.as_str(), self.scaled(rate).to_string()), (RATE_ALIAS_TOKEN, alias),
+             (CARD_TOKEN, self.card.clone()), (DEVICE_TOKEN, self.device.to_string()),
+             (SUBDEVICE_TOKEN, self.subdevice.to_string())]
+    }
+
+    // exec filled in only at start cannot be checked in advance
+    pub fn has_placeholder(&self, arg: &str) -> bool {
+        self.placeholders(0).iter().map(|(token, _)| *token).chain([PREV_RATE_TOKEN, ERROR_TOKEN, DIR_TOKEN, TLV_TOKEN])
+            .any(|token| arg.contains(token))
+    }
+
+    // non-integer scaled rate is refused at start, truncated here
+    fn scaled(&self, rate: usize) -> usize {
+        rate * self.rate_scale.num / self.rate_scale.den
     }
 }
 
-#[derive(Debug)]
+// inclusive LOW-HIGH rate ranges
+pub type RateRanges = Vec<(usize, usize)>;
+
+#[derive(Debug, Clone)]
 pub struct CmdCfg {
     exec: String,
     args: Vec<String>,
+    spawn: SpawnCfg,
+    // inclusive rate ranges the command overrides the default ones for, empty = default command
+    rates: RateRanges,
+    // args are a sh -c script, the substituted values are quoted
+    shell: bool,
 }
 
 impl CmdCfg {
-    pub fn new(program: String, args: Vec<String>) -> Self {
+    pub fn new(program: String, args: Vec<String>, spawn: SpawnCfg) -> Self {
         Self {
             exec: program,
-            args: args,
+            args,
+            spawn,
+            rates: Vec::new(),
+            shell: false,
+        }
+    }
+
+    pub fn in_shell(mut self, shell: bool) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    // command only for the rates, replacing the default commands
+    pub fn for_rates(mut self, rates: RateRanges) -> Self {
+        self.rates = rates;
+        self
+    }
+
+    fn overrides(&self, rate: usize) -> bool {
+        self.rates.iter().any(|(low, high)| (*low..=*high).contains(&rate))
+    }
+
+    // exec and args as started for the rate
+    pub fn cmdline(&self, rate: usize) -> Vec<String> {
+        self.cmdline_with(rate, &[])
+    }
+
+    // with extra placeholders of hook commands
+    fn cmdline_with(&self, rate: usize, extra: &[(&str, String)]) -> Vec<String> {
+        let mut values = self.spawn.placeholders(rate);
+        values.extend(extra.iter().cloned());
+        // e.g. a card name with spaces or an error message must not break the script
+        let arg_values = match self.shell {
+            true => values.iter().map(|(token, value)| (*token, cmdline::quote(value))).collect(),
+            false => values.clone(),
+        };
+        std::iter::once(substitute(&self.exec, &values))
+            .chain(self.args.iter().map(|s| substitute(s, &arg_values)))
+            .collect()
+    }
+}
+
+// commands started for the rate, the overriding ones if any match the rate, else the default ones
+pub fn rate_group(cmds: &[CmdCfg], rate: usize) -> Vec<&CmdCfg> {
+    let overridden = cmds.iter().any(|cmd| cmd.overrides(rate));
+    cmds.iter()
+        .filter(|cmd| match overridden {
+            true => cmd.overrides(rate),
+            false => cmd.rates.is_empty(),
+        })
+        .collect()
+}
+
+// exec thread of one direction, controlled by messages
+pub struct Executor {
+    dir: String,
+    sender: Sender<Msg>,
+    state: Arc<SharedState>,
+    thread: Option<JoinHandle<()>>,
+    // of the current config, updated by the reloads
+    quit_window: AtomicUsize,
+}
+
+impl Executor {
+    pub fn spawn(dir: &str, update: ExecUpdate, builtin: Option<LoopCfg>) -> Result<Self> {
+        let ExecUpdate { cfg, cmds: mut cmd_cfgs, hooks } = update;
+        let (sender, recv) = unbounded();
+        let state = Arc::new(SharedState::default());
+        let quit_window = AtomicUsize::new(cfg.quit_window());
+        let mut thread_data = ExecData::new(dir, cfg, recv, state.clone(), hooks, builtin);
+        let thread = thread::Builder::new()
+            .name(format!("{} Thread", dir))
+            .spawn(move || {
+                if let Err(err) = run_exec_thread(&mut thread_data, &mut cmd_cfgs) {
+                    error!("{}: exec thread failed: {:#}", thread_data.dir, err);
+                }
+            })?;
+        Ok(Executor { dir: dir.to_string(), sender, state, thread: Some(thread), quit_window })
+    }
+
+    pub fn send(&self, msg: Msg) -> Result<()> {
+        if let Msg::Reload(update) = &msg {
+            self.quit_window.store(update.cfg.quit_window(), Ordering::Relaxed);
+        }
+        Ok(self.sender.send(msg)?)
+    }
+
+    // for the control interfaces
+    pub fn sender(&self) -> &Sender<Msg> {
+        &self.sender
+    }
+
+    pub fn state(&self) -> &Arc<SharedState> {
+        &self.state
+    }
+
+    // stopping the exec and waiting for the thread to finish, a stuck thread is left to the process exit
+    pub fn quit(&mut self) -> Result<()> {
+        let _ = self.sender.send(Msg::Quit);
+        let window = self.quit_window.load(Ordering::Relaxed);
+        let deadline = Instant::now() + Duration::from_millis(window as u64);
+        while self.thread.as_ref().is_some_and(|thread| !thread.is_finished()) {
+            if Instant::now() >= deadline {
+                self.thread = None;
+                return Err(anyhow!("{}: Exec thread did not quit within {} ms", self.dir, window));
+            }
+            thread::sleep(QUIT_CHECK_INTERVAL);
+        }
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Err(_)) => Err(anyhow!("{}: Exec thread failed", self.dir)),
+            _ => Ok(()),
         }
     }
 }
 
-pub fn run_exec_thread(data: &mut ExecData, cmd: &mut CmdCfg) -> Result<()> {
+pub fn run_exec_thread(data: &mut ExecData, cmds: &mut Vec<CmdCfg>) -> Result<()> {
+    if data.cfg.takeover_dir.is_some() {
+        adopt(data, cmds);
+    }
     loop {
-        match data.recv.recv() {
+        data.state.set_busy(false);
+        let received = match (data.held.take(), next_deadline(data)) {
+            (Some(msg), _) => Ok(msg),
+            (None, Some(deadline)) => data.recv.recv_deadline(deadline),
+            (None, None) => data.recv.recv().map_err(RecvTimeoutError::from),
+        };
+        data.state.set_busy(true);
+        match received {
             Ok(msg) => {
-                match msg {
-                    Msg::StartExec(rate) => handle_new_rate(rate, data, cmd)?,
-                    Msg::StopExec => handle_new_rate(0, data, cmd)?,
-                    Msg::Quit => {
-                        debug!("Ordered to quit");
-                        kill_running_child(data)?;
-                        break;
+                let keep_running = match msg {
+                    Msg::StartExec(rate) => handle_new_rate(rate, data, cmds)?,
+                    Msg::StopExec => handle_new_rate(0, data, cmds)?,
+                    Msg::Restart => {
+                        restart(data, cmds)?;
+                        true
+                    }
+                    Msg::Flush => {
+                        debug!("{}: Nothing pending to flush", data.dir);
+                        true
+                    }
+                    Msg::Reload(update) => {
+                        data.pending_reload = Some(*update);
+                        true
                     }
+                    Msg::Tlv(tlv) => {
+                        run_tlv_hook(data, tlv);
+                        true
+                    }
+                    Msg::Quit => false,
+                };
+                if let Some(update) = data.pending_reload.take().filter(|_| keep_running) {
+                    reload(data, cmds, update)?;
+                }
+                if !keep_running {
+                    debug!("Ordered to quit");
+                    if data.cfg.takeover_dir.is_some() && !data.children.is_empty() {
+                        info!("{}: Leaving exec running for takeover", data.dir);
+                        data.children.clear();
+                    }
+                    kill_running_child(data)?;
+                    stop_idle(data);
+                    print_json_event(data, data.rate, "quit");
+                    set_state(data, ExecState::Stopped);
+                    data.stats.log_summary(&data.dir);
+                    info!("{}: Start latency histogram: {}", data.dir, data.state.latency.format());
+                    break;
                 }
             }
+            Err(RecvTimeoutError::Timeout) if runtime_deadline(data).is_some_and(|d| d <= Instant::now()) => {
+                info!("{}: Max runtime {}ms exceeded, stopping exec", data.dir, data.cfg.max_runtime);
+                kill_running_child(data)?;
+                let rate = data.rate;
+                data.rate = 0;
+                print_json_event(data, rate, "stop, max runtime exceeded");
+                set_state(data, ExecState::Stopped);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                check_gate(data, cmds)?;
+                check_binaries(data, cmds)?;
+                check_exited(data, cmds)?;
+            }
             Err(err) => {
                 error!("Message channel error: {}", err);
                 break;
@@ -79,88 +758,1712 @@ pub fn run_exec_thread(data: &mut ExecData, cmd: &mut CmdCfg) -> Result<()> {
     Ok(())
 }
 
-fn handle_new_rate(rate: usize, data: &mut ExecData, cmd: &mut CmdCfg) -> Result<()> {
+fn state_file(data: &ExecData) -> Option<PathBuf> {
+    data.cfg.takeover_dir.as_ref().map(|dir| Path::new(dir).join(format!("{}.state", data.dir)))
+}
+
+// adopting execs left running by the previous instance if still running the expected commands
+fn adopt(data: &mut ExecData, cmds: &[CmdCfg]) {
+    let path = match state_file(data) {
+        Some(path) => path,
+        None => return,
+    };
+    let state = match takeover::load(&path) {
+        Ok(Some(state)) => state,
+        Ok(None) => return,
+        Err(err) => {
+            warn!("{}: Cannot read takeover state {}, error: {}", data.dir, path.display(), err);
+            return;
+        }
+    };
+    let cmds = rate_group(cmds, state.rate);
+    let matching = state.pids.len() == cmds.len() && state.pids.iter().zip(cmds)
+        .all(|(pid, cmd)| takeover::cmdline_matches(*pid, &cmd.cmdline(state.rate)));
+    if matching {
+        info!("{}: Adopted exec pids {:?} running at rate {}", data.dir, state.pids, state.rate);
+        data.children = state.pids.into_iter().map(ExecChild::Adopted).collect();
+        data.rate = state.rate;
+        data.started_at = Some(Instant::now());
+        set_running_state(data);
+    } else {
+        info!("{}: Execs of takeover state {} not running anymore, not adopting", data.dir, path.display());
+        if let Err(err) = takeover::remove(&path) {
+            warn!("{}: Cannot remove takeover state, error: {}", data.dir, err);
+        }
+    }
+}
+
+fn next_deadline(data: &ExecData) -> Option<Instant> {
+    let gate_deadline = match data.cfg.gate_file {
+        Some(_) if data.gated || !data.children.is_empty() => Some(Instant::now() + GATE_CHECK_INTERVAL),
+        _ => None,
+    };
+    let binary_deadline = match data.cfg.watch_binary && !data.children.is_empty() {
+        true => Some(Instant::now() + BINARY_CHECK_INTERVAL),
+        false => None,
+    };
+    let exit_deadline = match data.cfg.restart_on_exit && !data.children.is_empty() {
+        true => Some(Instant::now() + EXIT_CHECK_INTERVAL),
+        false => data.restart_at,
+    };
+    [runtime_deadline(data), gate_deadline, binary_deadline, exit_deadline].into_iter().flatten().min()
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// restarting the running execs at the current rate when an executable was replaced, e.g. upgraded
+fn check_binaries(data: &mut ExecData, cmds: &mut [CmdCfg]) -> Result<()> {
+    if !data.cfg.watch_binary || data.children.is_empty() {
+        return Ok(());
+    }
+    let changed = data.binaries.iter().find(|(path, modified)| mtime(path) != *modified);
+    if let Some((path, _)) = changed {
+        info!("{}: Executable {} changed, restarting exec", data.dir, path.display());
+        restart(data, cmds)?;
+    }
+    Ok(())
+}
+
+// restarting the group at the current rate after a backoff when an exec exited, up to the max restarts in a row
+fn check_exited(data: &mut ExecData, cmds: &mut [CmdCfg]) -> Result<()> {
+    if !data.cfg.restart_on_exit {
+        return Ok(());
+    }
+    if let Some(restart_at) = data.restart_at {
+        if restart_at <= Instant::now() {
+            data.exit_restarts += 1;
+            warn!("{}: Restarting exited exec at rate {}, attempt {}", data.dir, data.rate, data.exit_restarts);
+            data.stats.restarts += 1;
+            Counters::inc(&data.state.counters.restarts);
+            start(data, cmds, data.rate, Instant::now());
+            print_json_event(data, data.rate, "restart of exited exec");
+            set_running_state(data);
+        }
+        return Ok(());
+    }
+    if data.children.is_empty() {
+        return Ok(());
+    }
+    let status = match exit_status(&mut data.children) {
+        Some(status) => status,
+        None => return Ok(()),
+    };
+    // a run longer than the max delay ends the episode, the next restart counts as the first one,
+    // with a margin for the exits noticed only at the checks
+    let stable = Duration::from_millis(data.cfg.backoff_max as u64) + 2 * EXIT_CHECK_INTERVAL;
+    if data.started_at.is_some_and(|started_at| started_at.elapsed() > stable) {
+        data.exit_restarts = 0;
+        data.exit_backoff = None;
+    }
+    kill_running_child(data)?;
+    if let Some(onerror) = data.hooks.onerror.as_mut() {
+        run_hook(onerror, data.rate, &[(ERROR_TOKEN, status.clone())], &data.dir, data.cfg.hook_timeout);
+    }
+    if data.cfg.max_restarts > 0 && data.exit_restarts >= data.cfg.max_restarts {
+        error!("{}: Exec exited with {}, max {} restarts reached, giving up until the next rate change",
+               data.dir, status, data.cfg.max_restarts);
+        Counters::inc(&data.state.counters.exit_giveups);
+        let rate = data.rate;
+        data.rate = 0;
+        print_json_event(data, rate, "failed, max restarts reached");
+        set_state(data, ExecState::Failed);
+        return Ok(());
+    }
+    let (base, max) = (data.cfg.backoff_base, data.cfg.backoff_max);
+    let delay = data.exit_backoff.get_or_insert_with(|| Backoff::new(base, max)).next_delay();
+    warn!("{}: Exec exited with {}, restarting at rate {} in {} ms", data.dir, status, data.rate, delay.as_millis());
+    data.restart_at = Some(Instant::now() + delay);
+    print_json_event(data, data.rate, "exec exited, restart pending");
+    set_running_state(data);
+    Ok(())
+}
+
+// status of the first exec which has finished, also of the adopted ones and of the builtin loop
+fn exit_status(children: &mut [ExecChild]) -> Option<String> {
+    children.iter_mut().find_map(|child| match child {
+        ExecChild::Spawned(child) => match child.try_wait() {
+            Ok(None) => None,
+            Ok(Some(status)) => Some(status.to_string()),
+            Err(err) => Some(err.to_string()),
+        },
+        // not a child of this instance, only its existence can be checked
+        ExecChild::Adopted(pid) => match kill(Pid::from_raw(*pid as i32), None) {
+            Err(Errno::ESRCH) => Some(format!("adopted PID {} gone", pid)),
+            _ => None,
+        },
+        ExecChild::Builtin(handle) if handle.finished() => Some("builtin loop finished".to_string()),
+        _ => None,
+    })
+}
+
+fn gate_open(data: &ExecData) -> bool {
+    data.cfg.gate_file.as_ref().is_none_or(|path| Path::new(path).exists())
+}
+
+// stopping the running exec when the gate closes, starting the deferred one when it opens
+fn check_gate(data: &mut ExecData, cmds: &mut [CmdCfg]) -> Result<()> {
+    let open = gate_open(data);
+    if !open && !data.children.is_empty() {
+        info!("{}: Gate closed, stopping exec", data.dir);
+        kill_running_child(data)?;
+        data.gated = true;
+        print_json_event(data, data.rate, "stop, gate closed");
+        set_running_state(data);
+    } else if open && data.gated && data.rate > 0 {
+        info!("{}: Gate opened, starting deferred exec at rate {}", data.dir, data.rate);
+        start(data, cmds, data.rate, Instant::now());
+        print_json_event(data, data.rate, "start, gate opened");
+        set_running_state(data);
+    }
+    Ok(())
+}
+
+// deadline for the running child, if limited
+fn runtime_deadline(data: &ExecData) -> Option<Instant> {
+    match (data.cfg.max_runtime, data.started_at) {
+        (max_runtime, Some(started_at)) if max_runtime > 0 && !data.children.is_empty() => {
+            Some(started_at + Duration::from_millis(max_runtime as u64))
+        }
+        _ => None,
+    }
+}
+
+// result of the stop grace window
+enum Grace {
+    Elapsed,
+    // by a nonzero rate
+    Cancelled(usize),
+    Quit,
+}
+
+// result of the debouncing window
+enum Debounced {
+    // latest rate requested within the window
+    Rate(usize),
+    Quit,
+}
+
+// reasoning of handling one rate, logged as a single line at the decision target
+struct Decision {
+    input: usize,
+    last: usize,
+    // after applying the rate tolerance
+    rate: usize,
+    kill: bool,
+    start: bool,
+    // latest rate after debouncing, None = not debounced
+    debounced: Option<usize>,
+}
+
+impl Decision {
+    fn log(&self, dir: &str, action: &str) {
+        let debounced = self.debounced.map_or("-".to_string(), |rate| rate.to_string());
+        trace!(target: "decision", "{}: input {}, last {}, tolerated {}, kill {}, start {}, debounced {}, action: {}",
+               dir, self.input, self.last, self.rate, self.kill, self.start, debounced, action);
+    }
+
+    fn action(&self, data: &ExecData) -> &'static str {
+        match (self.kill, self.start, data.rate) {
+            (false, false, _) => "none",
+            (true, false, _) | (true, true, 0) => "stop",
+            (false, true, 0) => "none, debounced to stop",
+            _ if data.gated => "start deferred by gate",
+            _ if data.children.is_empty() => "start failed",
+            (true, true, _) => "restart",
+            (false, true, _) => "start",
+        }
+    }
+}
+
+// returns false when ordered to quit
+fn handle_new_rate(rate: usize, data: &mut ExecData, cmds: &mut [CmdCfg]) -> Result<bool> {
     debug!("{}: Received new rate: {}", data.dir, rate);
+    let requested = Instant::now();
+    let input = rate;
+    let rate = within_tolerance(data.rate, rate, data.cfg.rate_tolerance);
+    if rate != data.rate {
+        data.exit_restarts = 0;
+        data.exit_backoff = None;
+        data.restart_at = None;
+    }
     let (do_kill, do_start) = decide_kill_run(data.rate, rate);
+    let mut decision = Decision { input, last: data.rate, rate, kill: do_kill, start: do_start, debounced: None };
+    // the running execs switch the rate on their own, debounced like the starts
+    let mut debounced = false;
+    if do_kill && do_start && reloadable(data, cmds, rate) {
+        if data.cfg.debounce_window() > 0 {
+            trace!("{}: Debouncing - delaying reload for {}ms", data.dir, data.cfg.debounce_window());
+            let latest = debounce(data, rate);
+            data.state.set_debounce_deadline(None);
+            match latest {
+                Debounced::Rate(latest) if latest != rate => {
+                    // stopped, resumed at the running rate or moved on
+                    decision.log(&data.dir, &format!("reload debounced to rate {}", latest));
+                    return handle_new_rate(latest, data, cmds);
+                }
+                Debounced::Rate(_) => debounced = true,
+                Debounced::Quit => {
+                    decision.log(&data.dir, "quit");
+                    return Ok(false);
+                }
+            }
+        }
+        if reload_children(data, rate) {
+            data.rate = rate;
+            decision.log(&data.dir, "reload");
+            print_json_event(data, input, "reload");
+            set_running_state(data);
+            return Ok(true);
+        }
+    }
     if do_kill {
+        if rate == 0 && data.cfg.stop_window() > 0 {
+            print_json_event(data, input, "stop deferred by grace");
+            match stop_grace(data) {
+                Grace::Elapsed => trace!("{}: Stop grace elapsed, stopping exec", data.dir),
+                Grace::Cancelled(new_rate) => {
+                    // the child keeps running unless the new rate differs
+                    trace!("{}: Stop grace cancelled by rate {}", data.dir, new_rate);
+                    if within_tolerance(data.rate, new_rate, data.cfg.rate_tolerance) == data.rate {
+                        debug!("{}: Resumed at rate {} before stopping, exec kept alive", data.dir, data.rate);
+                        data.stats.resumes += 1;
+                    }
+                    decision.log(&data.dir, &format!("stop cancelled by rate {}", new_rate));
+                    return handle_new_rate(new_rate, data, cmds);
+                }
+                Grace::Quit => {
+                    decision.log(&data.dir, "quit");
+                    return Ok(false);
+                }
+            }
+        }
         kill_running_child(data)?;
+        if do_start {
+            data.stats.restarts += 1;
+            Counters::inc(&data.state.counters.restarts);
+        } else if let Some(onstop) = data.hooks.onstop.as_mut() {
+            // the rate which was just running
+            let values = stop_values(onstop, data.rate);
+            run_hook(onstop, data.rate, &values, &data.dir, data.cfg.hook_timeout);
+        }
+        if !do_start {
+            start_idle(data);
+        }
     }
+    let mut rate = rate;
     if do_start {
-        // delaying to debounce
-        if data.timeout > 0 {
-            trace!("{}: Debouncing - delaying start for {}ms", data.dir, data.timeout);
-            data.debouncing_now.store(true, Ordering::SeqCst);
-            match data.timer.sleep(Duration::from_millis(data.timeout as u64)) {
-                Ok(_) => {
+        // delaying to debounce, unless already debounced before the failed reload
+        if data.cfg.debounce_window() > 0 && !debounced {
+            trace!("{}: Debouncing - delaying start for {}ms", data.dir, data.cfg.debounce_window());
+            set_state(data, ExecState::Debouncing);
+            let debounced = debounce(data, rate);
+            data.state.set_debounce_deadline(None);
+            if let Debounced::Rate(latest) = debounced {
+                decision.debounced = Some(latest);
+            }
+            match debounced {
+                Debounced::Rate(0) => {
+                    trace!("{}: Debouncing ended with stop, not starting exec", data.dir);
+                    Counters::inc(&data.state.counters.debounce_cancels);
+                    rate = 0;
+                }
+                Debounced::Rate(latest) => {
                     trace!("{}: Debouncing elapsed, starting exec", data.dir);
-                    data.child = start_child(cmd, rate);
+                    rate = latest;
+                    start(data, cmds, rate, requested);
                 }
-                Err(_) => {
-                    trace!("{}: Debouncing cancelled, not starting exec", data.dir);
+                Debounced::Quit => {
+                    decision.log(&data.dir, "quit");
+                    return Ok(false);
                 }
             }
-            data.debouncing_now.store(false, Ordering::SeqCst);
         } else {
             trace!("{}: Starting exec without debouncing", data.dir);
-            data.child = start_child(cmd, rate);
+            start(data, cmds, rate, requested);
         }
     }
     data.rate = rate;
-    Ok(())
+    if rate == 0 {
+        data.gated = false;
+    }
+    decision.log(&data.dir, decision.action(data));
+    print_json_event(data, input, decision.action(data));
+    set_running_state(data);
+    Ok(true)
 }
 
-// rate 0 = stop
-fn decide_kill_run(last_rate: usize, rate: usize) -> (bool, bool) {
-    let do_kill = /* any change in rate, unless it was zero */ last_rate > 0 && last_rate != rate;
-    let do_run = /* should run */ rate > 0 && (/* new start */  last_rate == 0 || /* restart */ do_kill);
-    (do_kill, do_run)
+// one line per handled rate and per exec action on stdout, for other tools
+fn print_json_event(data: &ExecData, rate: usize, action: &str) {
+    if !data.cfg.json_events {
+        return;
+    }
+    let line = json_event(data, rate, action);
+    let mut stdout = io::stdout().lock();
+    // a closed stdout must not stop the exec thread
+    let _ = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
 }
 
-fn kill_running_child(data: &mut ExecData) -> Result<(), std::io::Error> {
-    let option = data.child.as_mut();
-    if option.is_some() {
-        debug!("{}: killing exec", data.dir);
-        let child: &mut Child = option.unwrap();
-        if let Err(err) = kill_child(child) {
-            match (err).kind() {
-                // no problem
-                io::ErrorKind::InvalidInput => debug!("exec has already finished"),
-                _ => {
-                    // some other error, problem
-                    warn!("Cmd failed, error: {}", err);
-                    return Err(err);
-                }
-            }
-        }
-        data.child = None;
-    }
-    Ok(())
+fn json_event(data: &ExecData, rate: usize, action: &str) -> String {
+    let pids: Vec<String> = child_pids(&data.children).iter().map(u32::to_string).collect();
+    format!("{{\"timestamp\": {}, \"direction\": {}, \"rate\": {}, \"running_rate\": {}, \"action\": {}, \"pids\": [{}]}}",
+            json::quote(&humantime::format_rfc3339_millis(SystemTime::now()).to_string()),
+            json::quote(&data.dir), rate, data.rate, json::quote(action), pids.join(", "))
 }
 
-fn kill_child(child: &mut Child) -> Result<(), std::io::Error> {
-    child.kill()?;
-    child.wait()?;
-    Ok(())
+// reload configured and the running group also serving the new rate
+fn reloadable(data: &ExecData, cmds: &[CmdCfg], rate: usize) -> bool {
+    let old_group = rate_group(cmds, data.rate);
+    let new_group = rate_group(cmds, rate);
+    (data.cfg.reload_signal.is_some() || data.hooks.reload.is_some() || data.cfg.camilla.is_some())
+        && data.builtin.is_none()
+        && !data.children.is_empty()
+        && old_group.len() == new_group.len()
+        && old_group.iter().zip(new_group).all(|(old, new)| std::ptr::eq(*old, new))
 }
 
-fn start_child(cmd: &mut CmdCfg, rate: usize) -> Option<Child> {
-    // replacing RATE value in command args
-    let final_args: Vec<String> = cmd.args.iter().map(|s| {
-        if s.contains("{R}") {
-            s.replace("{R}", rate.to_string().as_str())
-        } else {
-            s.to_string()
+// false = the signal not delivered, the execs must be restarted
+fn reload_children(data: &mut ExecData, rate: usize) -> bool {
+    for child in data.children.iter_mut() {
+        if let ExecChild::Camilla(handle) = child {
+            if let Err(err) = handle.set_rate(rate, &data.dir) {
+                warn!("{}: CamillaDSP config switch failed, restarting, error: {:#}", data.dir, err);
+                return false;
+            }
         }
-    }).collect();
-    let child = match Command::new(&cmd.exec)
-        .args(&final_args)
-        .spawn() {
-        Ok(res) => Some(res),
-        Err(err) => {
-            warn!("Cmd failed, error: {}", err);
-            None
+    }
+    if let Some(sig) = data.cfg.reload_signal {
+        for child in data.children.iter() {
+            let result = match child {
+                ExecChild::Spawned(child) => signal_group(child, sig),
+                ExecChild::Adopted(pid) => kill(Pid::from_raw(*pid as i32), sig),
+                ExecChild::Simulated => {
+                    debug!("Dry run: sending {} to exec", sig);
+                    Ok(())
+                }
+                ExecChild::Builtin(_) | ExecChild::Camilla(_) => Ok(()),
+            };
+            if let Err(err) = result {
+                warn!("{}: Cannot send {} to exec, restarting it, error: {}", data.dir, sig, err);
+                return false;
+            }
         }
+    }
+    if let Some(reload) = data.hooks.reload.as_mut() {
+        let values = stop_values(reload, data.rate);
+        if !run_hook(reload, rate, &values, &data.dir, data.cfg.hook_timeout) {
+            warn!("{}: Reload command failed, restarting exec", data.dir);
+            return false;
+        }
+    }
+    info!("{}: Reloaded exec from rate {} to {}", data.dir, data.rate, rate);
+    data.stats.reloads += 1;
+    Counters::inc(&data.state.counters.reloads);
+    save_takeover_state(data, rate);
+    true
+}
+
+// coalescing all requests within the debouncing window into the latest one
+fn debounce(data: &mut ExecData, rate: usize) -> Debounced {
+    let window = Duration::from_millis(data.cfg.debounce_window() as u64);
+    let stable = data.cfg.debounce_mode == DebounceMode::Stable;
+    let max_deadline = Instant::now() + match stable {
+        true => Duration::from_millis(data.cfg.stable_max as u64).max(window),
+        false => window,
+    };
+    let mut deadline = Instant::now() + window;
+    data.state.set_debounce_deadline(Some(deadline));
+    let mut latest = rate;
+    loop {
+        let new_rate = match data.recv.recv_deadline(deadline) {
+            Ok(Msg::StartExec(new_rate)) => new_rate,
+            Ok(Msg::StopExec) => 0,
+            // nothing running yet
+            Ok(Msg::Restart) => continue,
+            Ok(Msg::Reload(update)) => {
+                data.pending_reload = Some(*update);
+                continue;
+            }
+            Ok(Msg::Tlv(tlv)) => {
+                run_tlv_hook(data, tlv);
+                continue;
+            }
+            Ok(Msg::Flush) => {
+                debug!("{}: Debouncing flushed", data.dir);
+                return Debounced::Rate(latest);
+            }
+            Ok(Msg::Quit) | Err(RecvTimeoutError::Disconnected) => return Debounced::Quit,
+            Err(RecvTimeoutError::Timeout) => {
+                if stable && deadline == max_deadline {
+                    info!("{}: Rate not stable within {}ms, using latest rate {}", data.dir, data.cfg.stable_max, latest);
+                }
+                return Debounced::Rate(latest);
+            }
+        };
+        if stable && new_rate != latest {
+            // waiting for the rate to settle again
+            deadline = (Instant::now() + window).min(max_deadline);
+            data.state.set_debounce_deadline(Some(deadline));
+        }
+        latest = new_rate;
+        trace!("{}: Debouncing - latest requested rate {}", data.dir, latest);
+    }
+}
+
+// the running exec restarted only when its commands changed
+fn reload(data: &mut ExecData, cmds: &mut Vec<CmdCfg>, update: ExecUpdate) -> Result<()> {
+    let cmdlines = |cmds: &[CmdCfg]| rate_group(cmds, data.rate).iter().map(|cmd| cmd.cmdline(data.rate)).collect::<Vec<Vec<String>>>();
+    let changed = cmdlines(cmds) != cmdlines(&update.cmds);
+    data.cfg = update.cfg;
+    data.hooks = update.hooks;
+    *cmds = update.cmds;
+    if changed && !data.children.is_empty() && data.builtin.is_none() && data.cfg.camilla.is_none() {
+        info!("{}: Commands changed by reload, restarting exec", data.dir);
+        restart(data, cmds)?;
+    } else {
+        debug!("{}: Config reloaded, exec untouched", data.dir);
+    }
+    Ok(())
+}
+
+// restarting the exec immediately at the current rate, no debouncing
+fn restart(data: &mut ExecData, cmds: &mut [CmdCfg]) -> Result<()> {
+    if data.rate == 0 {
+        debug!("{}: Not running, nothing to restart", data.dir);
+        return Ok(());
+    }
+    debug!("{}: Restarting exec at rate {}", data.dir, data.rate);
+    kill_running_child(data)?;
+    data.stats.restarts += 1;
+    Counters::inc(&data.state.counters.restarts);
+    start(data, cmds, data.rate, Instant::now());
+    set_running_state(data);
+    Ok(())
+}
+
+fn start(data: &mut ExecData, cmds: &mut [CmdCfg], rate: usize, requested: Instant) {
+    data.gated = !gate_open(data);
+    if data.gated {
+        info!("{}: Gate closed, deferring start at rate {}", data.dir, rate);
+        return;
+    }
+    stop_idle(data);
+    data.restart_at = None;
+    let started = match (&data.builtin, &data.cfg.camilla) {
+        (Some(cfg), _) => start_builtin(cfg, rate, &data.dir),
+        (None, Some(cfg)) => start_camilla(cfg, rate, &data.dir),
+        (None, None) => start_group(cmds, rate, &data.dir, &data.cfg),
     };
-    debug!("Started: exec {}, args: {:#?}", cmd.exec, final_args);
-    child
-}
\ No newline at end of file
+    data.started_at = Some(Instant::now());
+    let failure = match started {
+        Ok(children) => {
+            data.children = children;
+            match data.cfg.confirm > 0 && !data.children.is_empty() {
+                true => confirm_running(data, rate),
+                false => None,
+            }
+        }
+        Err(err) => Some(err),
+    };
+    if failure.is_some() {
+        Counters::inc(&data.state.counters.spawn_failures);
+    }
+    if let (Some(err), Some(onerror)) = (failure, data.hooks.onerror.as_mut()) {
+        run_hook(onerror, rate, &[(ERROR_TOKEN, err)], &data.dir, data.cfg.hook_timeout);
+    }
+    if data.children.is_empty() {
+        return;
+    }
+    if data.cfg.watch_binary && data.builtin.is_none() && data.cfg.camilla.is_none() {
+        data.binaries = rate_group(cmds, rate).iter()
+            .filter_map(|cmd| resolve_exec(&cmd.cmdline(rate)[0]))
+            .map(|path| {
+                let modified = mtime(&path);
+                (path, modified)
+            })
+            .collect();
+    }
+    save_takeover_state(data, rate);
+    let duration = Instant::now() - requested;
+    data.stats.starts += 1;
+    Counters::inc(&data.state.counters.starts);
+    data.stats.latency += duration;
+    data.state.latency.record(duration);
+    if data.cfg.show_timing {
+        info!("{}: START spawned after {} ms", data.dir, duration.as_millis());
+    }
+}
+
+// group with an exec died within the confirm time is killed as a failed start, returns the exit status
+// a message received meanwhile ends the wait early, the exec is confirmed by then
+fn confirm_running(data: &mut ExecData, rate: usize) -> Option<String> {
+    set_state(data, ExecState::Starting(rate));
+    let deadline = Instant::now() + Duration::from_millis(data.cfg.confirm as u64);
+    let died = loop {
+        let died = exit_status(&mut data.children);
+        let now = Instant::now();
+        if died.is_some() || now >= deadline {
+            break died;
+        }
+        match data.recv.recv_deadline(deadline.min(now + CONFIRM_CHECK_INTERVAL)) {
+            Err(RecvTimeoutError::Timeout) => {}
+            // arriving along with the rate change, not ending the wait
+            Ok(Msg::Tlv(tlv)) => run_tlv_hook(data, tlv),
+            Ok(msg) => {
+                data.held = Some(msg);
+                break exit_status(&mut data.children);
+            }
+            Err(RecvTimeoutError::Disconnected) => break exit_status(&mut data.children),
+        }
+    };
+    match &died {
+        Some(status) => {
+            warn!("{}: Exec died within {}ms after start with {}, start failed", data.dir, data.cfg.confirm, status);
+            let _ = kill_children(&mut data.children, &data.cfg);
+            data.children.clear();
+        }
+        None => trace!("{}: Exec confirmed running after {}ms", data.dir, data.cfg.confirm),
+    }
+    died
+}
+
+// waiting on the channel, the first nonzero rate cancels the stop
+fn stop_grace(data: &mut ExecData) -> Grace {
+    trace!("{}: Stop grace - delaying stop for {}ms", data.dir, data.cfg.stop_window());
+    let deadline = Instant::now() + Duration::from_millis(data.cfg.stop_window() as u64);
+    loop {
+        match data.recv.recv_deadline(deadline) {
+            Ok(Msg::StartExec(new_rate)) if new_rate > 0 => return Grace::Cancelled(new_rate),
+            // already stopping
+            Ok(Msg::StartExec(_)) | Ok(Msg::StopExec) | Ok(Msg::Restart) => {}
+            Ok(Msg::Reload(update)) => data.pending_reload = Some(*update),
+            Ok(Msg::Tlv(tlv)) => run_tlv_hook(data, tlv),
+            Ok(Msg::Flush) => {
+                debug!("{}: Stop grace flushed", data.dir);
+                return Grace::Elapsed;
+            }
+            Ok(Msg::Quit) | Err(RecvTimeoutError::Disconnected) => return Grace::Quit,
+            Err(RecvTimeoutError::Timeout) => return Grace::Elapsed,
+        }
+    }
+}
+
+fn set_running_state(data: &mut ExecData) {
+    let new_state = match data.children.is_empty() {
+        false => ExecState::Running(data.rate),
+        true => ExecState::Stopped,
+    };
+    set_state(data, new_state);
+}
+
+fn set_state(data: &mut ExecData, new_state: ExecState) {
+    // before the state change, watchers see the new PIDs
+    update_pids(data);
+    if let Some(old_state) = data.state.set(new_state) {
+        info!("{}: {} -> {}", data.dir, old_state, new_state);
+    }
+}
+
+fn child_pids(children: &[ExecChild]) -> Vec<u32> {
+    children.iter()
+        .filter_map(|child| match child {
+            ExecChild::Spawned(child) => Some(child.id()),
+            ExecChild::Adopted(pid) => Some(*pid),
+            _ => None,
+        })
+        .collect()
+}
+
+fn update_pids(data: &ExecData) {
+    let pids = child_pids(&data.children);
+    if !data.state.set_pids(pids.clone()) {
+        return;
+    }
+    if let Some(path) = &data.cfg.pid_file {
+        if let Err(err) = write_pid_file(path, &pids) {
+            warn!("{}: Cannot write PID file {}, error: {}", data.dir, path, err);
+        }
+    }
+}
+
+// replaced atomically, removed when no exec runs
+fn write_pid_file(path: &str, pids: &[u32]) -> io::Result<()> {
+    if pids.is_empty() {
+        return match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    let tmp = format!("{}.tmp", path);
+    let line: Vec<String> = pids.iter().map(u32::to_string).collect();
+    fs::write(&tmp, format!("{}\n", line.join(" ")))?;
+    fs::rename(&tmp, path)
+}
+
+fn save_takeover_state(data: &ExecData, rate: usize) {
+    let path = match state_file(data) {
+        Some(path) => path,
+        None => return,
+    };
+    // simulated execs cannot be adopted
+    let pids: Vec<u32> = data.children.iter()
+        .filter_map(|child| match child {
+            ExecChild::Spawned(child) => Some(child.id()),
+            _ => None,
+        })
+        .collect();
+    if pids.len() != data.children.len() {
+        return;
+    }
+    if let Err(err) = takeover::save(&path, rate, &pids) {
+        warn!("{}: Cannot save takeover state {}, error: {}", data.dir, path.display(), err);
+    }
+}
+
+// running rate when the new one differs by the tolerance at most, e.g. clock drift reporting noise
+fn within_tolerance(last_rate: usize, rate: usize, tolerance: usize) -> usize {
+    if last_rate > 0 && rate > 0 && rate != last_rate && rate.abs_diff(last_rate) <= tolerance {
+        debug!("Rate {} within tolerance {} of running rate {}, unchanged", rate, tolerance, last_rate);
+        return last_rate;
+    }
+    rate
+}
+
+// rate 0 = stop
+fn decide_kill_run(last_rate: usize, rate: usize) -> (bool, bool) {
+    let do_kill = /* any change in rate, unless it was zero */ last_rate > 0 && last_rate != rate;
+    let do_run = /* should run */ rate > 0 && (/* new start */  last_rate == 0 || /* restart */ do_kill);
+    (do_kill, do_run)
+}
+
+fn kill_running_child(data: &mut ExecData) -> Result<(), std::io::Error> {
+    if data.children.is_empty() {
+        return Ok(());
+    }
+    debug!("{}: killing exec", data.dir);
+    let result = kill_children(&mut data.children, &data.cfg);
+    data.children.clear();
+    if let Some(path) = state_file(data) {
+        if let Err(err) = takeover::remove(&path) {
+            warn!("{}: Cannot remove takeover state, error: {}", data.dir, err);
+        }
+    }
+    data.stats.stops += 1;
+    Counters::inc(&data.state.counters.stops);
+    if let Some(started_at) = data.started_at {
+        data.stats.runtime += started_at.elapsed();
+    }
+    result
+}
+
+fn kill_children(children: &mut [ExecChild], cfg: &ExecCfg) -> Result<(), std::io::Error> {
+    if cfg.stop_signal != Signal::SIGKILL && cfg.kill_timeout > 0 {
+        terminate_children(children, cfg);
+    }
+    let mut result = Ok(());
+    for child in children {
+        if let Err(err) = kill_child(child) {
+            match (err).kind() {
+                // no problem
+                io::ErrorKind::InvalidInput => debug!("exec has already finished"),
+                _ => {
+                    // some other error, problem
+                    warn!("Cmd failed, error: {}", err);
+                    result = Err(err);
+                }
+            }
+        }
+    }
+    result
+}
+
+// all signalled first, the whole group gets one grace period to close its devices, the rest is killed
+fn terminate_children(children: &mut [ExecChild], cfg: &ExecCfg) {
+    let mut signalled: Vec<&mut Child> = Vec::new();
+    let mut adopted = Vec::new();
+    for child in children.iter_mut() {
+        match child {
+            ExecChild::Spawned(child) if signal_group(child, cfg.stop_signal).is_ok() => signalled.push(child),
+            ExecChild::Adopted(pid) if kill(Pid::from_raw(*pid as i32), cfg.stop_signal).is_ok() => adopted.push(*pid),
+            _ => {}
+        }
+    }
+    let deadline = Instant::now() + Duration::from_millis(cfg.kill_timeout as u64);
+    for child in signalled {
+        let left_ms = deadline.saturating_duration_since(Instant::now()).as_millis() as usize;
+        match wait_timeout(child, left_ms.max(1)) {
+            Ok(Some(status)) => debug!("Exec {} finished with {} after {}", child.id(), status, cfg.stop_signal),
+            Ok(None) => warn!("Exec {} not finished within {}ms after {}, killing", child.id(), cfg.kill_timeout, cfg.stop_signal),
+            Err(err) => debug!("Cannot wait for exec {}, error: {}", child.id(), err),
+        }
+    }
+    // not children of this instance, polled
+    for pid in adopted {
+        match takeover::wait_gone(pid, deadline) {
+            true => debug!("Adopted exec {} finished after {}", pid, cfg.stop_signal),
+            false => warn!("Adopted exec {} not finished within {}ms after {}, killing", pid, cfg.kill_timeout, cfg.stop_signal),
+        }
+    }
+}
+
+// the exec with its descendants, e.g. of a wrapper script
+fn signal_group(child: &Child, signal: Signal) -> nix::Result<()> {
+    killpg(Pid::from_raw(child.id() as i32), signal)
+}
+
+fn kill_child(child: &mut ExecChild) -> Result<(), std::io::Error> {
+    match child {
+        ExecChild::Spawned(child) => {
+            if signal_group(child, Signal::SIGKILL).is_err() {
+                child.kill()?;
+            }
+            child.wait()?;
+        }
+        ExecChild::Simulated => debug!("Dry run: killing exec"),
+        ExecChild::Adopted(pid) => takeover::kill_adopted(*pid)?,
+        ExecChild::Builtin(handle) => handle.stop(),
+        ExecChild::Camilla(handle) => handle.stop(),
+    }
+    Ok(())
+}
+
+// failure returned as the error text for the error hook
+fn start_builtin(cfg: &LoopCfg, rate: usize, dir: &str) -> Result<Vec<ExecChild>, String> {
+    if cfg.dry_run {
+        info!("{}: Dry run: builtin loop {} -> {} at rate {}", dir, cfg.capture_dev, cfg.playback_dev, rate);
+        return Ok(vec![ExecChild::Simulated]);
+    }
+    match builtin_loop::start(cfg, rate, dir) {
+        Ok(handle) => Ok(vec![ExecChild::Builtin(handle)]),
+        Err(err) => {
+            warn!("{}: Builtin loop failed, error: {:#}", dir, err);
+            Err(format!("{:#}", err))
+        }
+    }
+}
+
+// failure returned as the error text for the error hook
+fn start_camilla(cfg: &CamillaCfg, rate: usize, dir: &str) -> Result<Vec<ExecChild>, String> {
+    if cfg.dry_run {
+        info!("{}: Dry run: CamillaDSP {} config {} at rate {}", dir, cfg.addr, cfg.config, rate);
+        return Ok(vec![ExecChild::Simulated]);
+    }
+    match camilla::start(cfg, rate, dir) {
+        Ok(handle) => Ok(vec![ExecChild::Camilla(handle)]),
+        Err(err) => {
+            warn!("{}: CamillaDSP start failed, error: {:#}", dir, err);
+            Err(format!("{:#}", err))
+        }
+    }
+}
+
+// all or none of the group commands are started, failure returned as the error text for the error hook
+fn start_group(cmds: &[CmdCfg], rate: usize, dir: &str, cfg: &ExecCfg) -> Result<Vec<ExecChild>, String> {
+    let cmds = rate_group(cmds, rate);
+    if let Some(cmd) = cmds.iter().find(|cmd| cmd.spawn.rate_scale.apply(rate).is_none()) {
+        let err = format!("Rate {} scaled by {} is not an integer", rate, cmd.spawn.rate_scale);
+        warn!("{}: {}, not starting", dir, err);
+        return Err(err);
+    }
+    let mut children = Vec::new();
+    for cmd in cmds {
+        match start_child(cmd, rate, &[], dir) {
+            Ok(child) => children.push(child),
+            Err(err) => {
+                let err = format!("{:#}", anyhow::Error::from(err));
+                warn!("{}: Cmd failed, error: {}", dir, err);
+                if !children.is_empty() {
+                    warn!("{}: Killing {} already started exec(s) of the failed group", dir, children.len());
+                    let _ = kill_children(&mut children, cfg);
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(children)
+}
+
+// the idle command gets the stopped rate like stop hooks
+fn start_idle(data: &mut ExecData) {
+    let rate = data.rate;
+    let idle = match data.hooks.idle.as_mut() {
+        Some(idle) => idle,
+        None => return,
+    };
+    let values = stop_values(idle, rate);
+    match start_child(idle, rate, &values, &data.dir) {
+        Ok(child) => {
+            debug!("{}: Idle command started", data.dir);
+            data.idle = Some(child);
+        }
+        Err(err) => warn!("{}: Idle command failed, error: {:#}", data.dir, anyhow::Error::from(err)),
+    }
+}
+
+fn stop_idle(data: &mut ExecData) {
+    if let Some(mut child) = data.idle.take() {
+        debug!("{}: Killing idle command", data.dir);
+        if let Err(err) = kill_child(&mut child) {
+            debug!("{}: Idle command already finished, error: {}", data.dir, err);
+        }
+    }
+}
+
+// stop hooks get the stopped rate also as {PREV_R}
+fn stop_values(cmd: &CmdCfg, rate: usize) -> Vec<(&'static str, String)> {
+    vec![(PREV_RATE_TOKEN, cmd.spawn.scaled(rate).to_string())]
+}
+
+fn start_child(cmd: &CmdCfg, rate: usize, extra: &[(&str, String)], dir: &str) -> Result<ExecChild, Error> {
+    // replacing RATE value in command exec and args
+    let mut final_args = cmd.cmdline_with(rate, extra);
+    let exec = final_args.remove(0);
+    if cmd.spawn.dry_run {
+        info!("{}: Dry run: exec {}, args: {:?}", dir, exec, final_args);
+        return Ok(ExecChild::Simulated);
+    }
+    if !exec_exists(&exec) {
+        return Err(Error::ExecNotFound(exec));
+    }
+    let mut command = Command::new(&exec);
+    // own process group, stopping also the processes started by the exec
+    command.args(&final_args)
+        .stdout(stdio(cmd.spawn.stdout))
+        .stderr(stdio(cmd.spawn.stderr))
+        .process_group(0);
+    if let Some(name) = &cmd.spawn.rate_env {
+        command.env(name, cmd.spawn.scaled(rate).to_string());
+    }
+    let mut child = command.spawn()
+        .map_err(|source| Error::Spawn { exec: exec.clone(), source })?;
+    if let Some(stdout) = child.stdout.take() {
+        log_lines(stdout, dir, cmd.spawn.log_level);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let level = if cmd.spawn.journald { cmd.spawn.log_level.min(Level::Warn) } else { cmd.spawn.log_level };
+        log_lines(stderr, dir, level);
+    }
+    debug!("Started: exec {}, args: {:#?}", exec, final_args);
+    Ok(ExecChild::Spawned(child))
+}
+
+// synchronous hook command, killed when exceeding the timeout
+// false when the hook failed to run, exited with an error or timed out
+fn run_hook(cmd: &mut CmdCfg, rate: usize, extra: &[(&str, String)], dir: &str, timeout: usize) -> bool {
+    let mut child = match start_child(cmd, rate, extra, dir) {
+        Ok(ExecChild::Spawned(child)) => child,
+        Ok(_) => return true,
+        Err(err) => {
+            warn!("{}: Hook failed, error: {:#}", dir, anyhow::Error::from(err));
+            return false;
+        }
+    };
+    match wait_timeout(&mut child, timeout) {
+        Ok(Some(status)) => {
+            debug!("{}: Hook {} finished with {}", dir, cmd.exec, status);
+            status.success()
+        }
+        Ok(None) => {
+            warn!("{}: Hook {} exceeded timeout {}ms, killing", dir, cmd.exec, timeout);
+            let _ = signal_group(&child, Signal::SIGKILL);
+            let _ = child.wait();
+            false
+        }
+        Err(err) => {
+            warn!("{}: Hook {} failed, error: {}", dir, cmd.exec, err);
+            false
+        }
+    }
+}
+
+fn run_tlv_hook(data: &mut ExecData, tlv: String) {
+    if let Some(hook) = data.hooks.tlv.as_mut() {
+        run_hook(hook, data.rate, &[(DIR_TOKEN, data.dir.clone()), (TLV_TOKEN, tlv)], &data.dir, data.cfg.hook_timeout);
+    }
+}
+
+// None when the timeout elapsed
+fn wait_timeout(child: &mut Child, timeout: usize) -> io::Result<Option<ExitStatus>> {
+    if timeout == 0 {
+        return child.wait().map(Some);
+    }
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn stdio(output: ChildOutput) -> Stdio {
+    match output {
+        ChildOutput::Inherit => Stdio::inherit(),
+        ChildOutput::Log => Stdio::piped(),
+        ChildOutput::Null => Stdio::null(),
+    }
+}
+
+// the thread finishes when the child closes the stream, named by the direction for its log level
+fn log_lines<R: Read + Send + 'static>(stream: R, dir: &str, level: Level) {
+    let thread_dir = dir.to_string();
+    let res = thread::Builder::new()
+        .name(format!("{} Output Thread", dir))
+        .spawn(move || {
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                log!(level, "[{}] {}", thread_dir, line);
+            }
+        });
+    if let Err(err) = res {
+        warn!("{}: Cannot log exec output, error: {}", dir, err);
+    }
+}
+
+fn substitute(s: &str, values: &[(&str, String)]) -> String {
+    values.iter().fold(s.to_string(), |s, (token, value)| {
+        if s.contains(token) {
+            s.replace(token, value)
+        } else {
+            s
+        }
+    })
+}
+
+// exec with a path must exist, plain exec name is searched in PATH
+pub fn exec_exists(exec: &str) -> bool {
+    resolve_exec(exec).is_some()
+}
+
+fn resolve_exec(exec: &str) -> Option<PathBuf> {
+    if exec.contains('/') {
+        return Some(PathBuf::from(exec)).filter(|path| path.is_file());
+    }
+    env::var_os("PATH")
+        .and_then(|paths| env::split_paths(&paths).map(|dir| dir.join(exec)).find(|path| path.is_file()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::builtin_loop::LoopFormat;
+
+    use super::*;
+
+    use self::Wait::{Ms, State};
+
+    fn exec_cfg(timeout: usize, stop_grace: usize) -> ExecCfg {
+        ExecCfg { timeout, stop_grace, keepalive: 0, show_timing: false, max_runtime: 0, hook_timeout: 0,
+                  takeover_dir: None, gate_file: None, confirm: 0, debounce_mode: DebounceMode::Fixed, stable: 0, stable_max: 0,
+                  rate_tolerance: 0, watch_binary: false, restart_on_exit: false, max_restarts: 0, backoff_base: 10, backoff_max: 50, pid_file: None,
+                  stop_signal: Signal::SIGTERM, kill_timeout: 1000, reload_signal: None, camilla: None,
+                  json_events: false }
+    }
+
+    // harmless long running child, killed by the executor
+    fn sleep_cmd() -> CmdCfg {
+        let spawn = SpawnCfg {
+            rate_token: "{R}".to_string(),
+            rate_env: None,
+            rate_aliases: HashMap::new(),
+            stdout: ChildOutput::Null,
+            stderr: ChildOutput::Null,
+            log_level: Level::Info,
+            dry_run: false,
+            journald: false,
+            rate_scale: RateScale::default(),
+            card: "UAC2Gadget".to_string(),
+            device: 0,
+            subdevice: 0,
+        };
+        CmdCfg::new("sleep".to_string(), vec!["10".to_string()], spawn)
+    }
+
+    // longest wait for a state or a condition, generous for loaded machines
+    const TEST_DEADLINE: Duration = Duration::from_secs(10);
+
+    // what the test waits for after sending a message
+    enum Wait {
+        // fixed pause, only for timing within the debouncing/grace windows
+        Ms(u64),
+        // state reported by the exec thread
+        State(ExecState),
+        // polled condition, e.g. a file written by a command
+        Until(Box<dyn Fn() -> bool>),
+    }
+
+    fn running(rate: usize) -> Wait {
+        Wait::State(ExecState::Running(rate))
+    }
+
+    // some content written to the file
+    fn file_written(path: &Path) -> impl Fn() -> bool {
+        let path = path.to_path_buf();
+        move || fs::metadata(&path).is_ok_and(|meta| meta.len() > 0)
+    }
+
+    fn written(path: &Path) -> Wait {
+        Wait::Until(Box::new(file_written(path)))
+    }
+
+    fn wait_until(cond: impl Fn() -> bool) {
+        let deadline = Instant::now() + TEST_DEADLINE;
+        while !cond() {
+            assert!(Instant::now() < deadline, "condition not met within {:?}", TEST_DEADLINE);
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    // by the mask of ignored signals of the process
+    fn ignores_term(pid: u32) -> bool {
+        fs::read_to_string(format!("/proc/{}/status", pid)).ok()
+            .and_then(|status| status.lines()
+                .find_map(|line| line.strip_prefix("SigIgn:"))
+                .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok()))
+            .is_some_and(|mask| mask & (1 << (Signal::SIGTERM as u64 - 1)) != 0)
+    }
+
+    // exec thread driven by the test, with a separate state watcher for the waiting
+    struct TestExec {
+        sender: Sender<Msg>,
+        state: Arc<SharedState>,
+        progress: Receiver<ExecState>,
+        states: Receiver<ExecState>,
+        thread: JoinHandle<ExecData>,
+    }
+
+    impl TestExec {
+        fn spawn(cfg: ExecCfg, hooks: Hooks, builtin: Option<LoopCfg>, mut cmds: Vec<CmdCfg>) -> Self {
+            let (sender, recv) = unbounded();
+            let state = Arc::new(SharedState::default());
+            let (progress, states) = (state.watch(), state.watch());
+            let mut data = ExecData::new("Test", cfg, recv, state.clone(), hooks, builtin);
+            let thread = thread::spawn(move || {
+                run_exec_thread(&mut data, &mut cmds).unwrap();
+                data
+            });
+            TestExec { sender, state, progress, states, thread }
+        }
+
+        fn send(&self, msg: Msg, wait: Wait) {
+            self.sender.send(msg).unwrap();
+            self.wait(wait);
+        }
+
+        fn wait(&self, wait: Wait) {
+            match wait {
+                Wait::Ms(ms) => thread::sleep(Duration::from_millis(ms)),
+                Wait::State(expected) => {
+                    let deadline = Instant::now() + TEST_DEADLINE;
+                    loop {
+                        match self.progress.recv_deadline(deadline) {
+                            Ok(state) if state == expected => break,
+                            Ok(_) => {}
+                            Err(err) => panic!("state {} not reached: {}", expected, err),
+                        }
+                    }
+                }
+                Wait::Until(cond) => wait_until(cond),
+            }
+        }
+
+        fn quit(self) -> (ExecData, Vec<ExecState>) {
+            self.sender.send(Msg::Quit).unwrap();
+            let data = self.thread.join().unwrap();
+            (data, self.states.try_iter().collect())
+        }
+    }
+
+    // feeds the messages to the exec thread, each followed by its wait, then quits
+    fn run(cfg: ExecCfg, msgs: Vec<(Msg, Wait)>) -> (ExecData, Vec<ExecState>) {
+        run_cmds(cfg, vec![sleep_cmd()], msgs)
+    }
+
+    fn run_cmds(cfg: ExecCfg, cmds: Vec<CmdCfg>, msgs: Vec<(Msg, Wait)>) -> (ExecData, Vec<ExecState>) {
+        run_hooked(cfg, Hooks::default(), cmds, msgs)
+    }
+
+    fn run_hooked(cfg: ExecCfg, hooks: Hooks, cmds: Vec<CmdCfg>, msgs: Vec<(Msg, Wait)>)
+                  -> (ExecData, Vec<ExecState>) {
+        run_builtin(cfg, hooks, None, cmds, msgs)
+    }
+
+    fn run_builtin(cfg: ExecCfg, hooks: Hooks, builtin: Option<LoopCfg>, cmds: Vec<CmdCfg>, msgs: Vec<(Msg, Wait)>)
+                   -> (ExecData, Vec<ExecState>) {
+        let exec = TestExec::spawn(cfg, hooks, builtin, cmds);
+        for (msg, wait) in msgs {
+            exec.send(msg, wait);
+        }
+        exec.quit()
+    }
+
+    #[test]
+    fn starts_and_stops() {
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StopExec, Ms(0))];
+        let (data, states) = run(exec_cfg(0, 0), msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.stops), (1, 1));
+    }
+
+    #[test]
+    fn rate_change_restarts() {
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(44100), running(44100))];
+        let (data, states) = run(exec_cfg(0, 0), msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Running(44100), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.restarts), (2, 1));
+    }
+
+    #[test]
+    fn reload_signal_replaces_restart() {
+        let mut cfg = exec_cfg(0, 0);
+        // harmless for sleep
+        cfg.reload_signal = Some(Signal::SIGCONT);
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(44100), running(44100))];
+        let (data, states) = run(cfg, msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Running(44100), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.restarts, data.stats.reloads), (1, 0, 1));
+    }
+
+    #[test]
+    fn json_event_fields() {
+        let (_sender, recv) = unbounded();
+        let mut data = ExecData::new("Test", exec_cfg(0, 0), recv, Arc::new(SharedState::default()), Hooks::default(), None);
+        data.rate = 44100;
+        data.children = vec![ExecChild::Adopted(4711)];
+        let event = json_event(&data, 48000, "restart");
+        assert!(event.starts_with("{\"timestamp\": \"20"));
+        assert!(event.ends_with("Z\", \"direction\": \"Test\", \"rate\": 48000, \"running_rate\": 44100, \"action\": \"restart\", \"pids\": [4711]}"));
+        // not killed at drop
+        data.children.clear();
+    }
+
+    #[test]
+    fn failed_reload_cmd_restarts() {
+        let mut reload = sleep_cmd();
+        reload.exec = "false".to_string();
+        reload.args = Vec::new();
+        let hooks = Hooks { onstop: None, onerror: None, idle: None, reload: Some(reload), tlv: None };
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(44100), running(44100))];
+        let (data, _) = run_hooked(exec_cfg(0, 0), hooks, vec![sleep_cmd()], msgs);
+        assert_eq!((data.stats.starts, data.stats.restarts, data.stats.reloads), (2, 1, 0));
+    }
+
+    #[test]
+    fn reload_debounced_to_latest_rate() {
+        let mut cfg = exec_cfg(50, 0);
+        cfg.reload_signal = Some(Signal::SIGCONT);
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(44100), Ms(0)),
+                        (Msg::StartExec(96000), running(96000))];
+        let (data, states) = run(cfg, msgs);
+        assert_eq!(states, vec![ExecState::Debouncing, ExecState::Running(48000), ExecState::Running(96000), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.restarts, data.stats.reloads), (1, 0, 1));
+    }
+
+    #[test]
+    fn same_rate_keeps_running() {
+        let (data, _) = run(exec_cfg(0, 0), vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(48000), Ms(0))]);
+        assert_eq!((data.stats.starts, data.stats.restarts), (1, 0));
+    }
+
+    #[test]
+    fn rate_within_tolerance_keeps_running() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.rate_tolerance = 2;
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(48001), Ms(0)),
+                        (Msg::StartExec(47999), Ms(0))];
+        let (data, states) = run(cfg.clone(), msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.restarts), (1, 0));
+        let (data, _) = run(cfg, vec![(Msg::StartExec(48000), running(48000)), (Msg::StartExec(48003), running(48003))]);
+        assert_eq!((data.stats.starts, data.stats.restarts), (2, 1));
+    }
+
+    #[test]
+    fn debounce_coalesces_to_latest_rate() {
+        let msgs = vec![(Msg::StartExec(48000), Ms(0)), (Msg::StopExec, Ms(0)), (Msg::StartExec(96000), running(96000))];
+        let (data, states) = run(exec_cfg(50, 0), msgs);
+        assert_eq!(states, vec![ExecState::Debouncing, ExecState::Running(96000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 1);
+    }
+
+    #[test]
+    fn stable_debounce_waits_for_settled_rate() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.debounce_mode = DebounceMode::Stable;
+        cfg.stable = 100;
+        cfg.stable_max = 10000;
+        // each change within the window restarts it, beyond a fixed window of the same length
+        let msgs = vec![(Msg::StartExec(48000), Ms(70)), (Msg::StartExec(44100), Ms(70)), (Msg::StartExec(96000), Ms(70)),
+                        (Msg::StartExec(48000), running(48000))];
+        let (data, states) = run(cfg, msgs);
+        assert_eq!(states, vec![ExecState::Debouncing, ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 1);
+    }
+
+    #[test]
+    fn stable_debounce_capped_by_max_wait() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.debounce_mode = DebounceMode::Stable;
+        cfg.stable = 100;
+        cfg.stable_max = 150;
+        let msgs = vec![(Msg::StartExec(48000), Ms(70)), (Msg::StartExec(44100), Ms(70)),
+                        (Msg::StartExec(96000), running(96000))];
+        let (_, states) = run(cfg, msgs);
+        assert_eq!(states, vec![ExecState::Debouncing, ExecState::Running(96000), ExecState::Stopped]);
+    }
+
+    #[test]
+    fn debounce_ending_with_stop_does_not_start() {
+        let msgs = vec![(Msg::StartExec(48000), Ms(0)), (Msg::StopExec, State(ExecState::Stopped))];
+        let (data, states) = run(exec_cfg(50, 0), msgs);
+        assert_eq!(states, vec![ExecState::Debouncing, ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 0);
+    }
+
+    #[test]
+    fn stop_grace_cancelled_by_same_rate() {
+        // no stop after the cancelled grace period
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StopExec, Ms(20)), (Msg::StartExec(48000), Ms(200))];
+        let (data, states) = run(exec_cfg(0, 100), msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.stops), (1, 1));
+    }
+
+    #[test]
+    fn idle_cmd_runs_while_stopped() {
+        let out = env::temp_dir().join(format!("gaudio_ctl_idle_{}", std::process::id()));
+        let mut idle = sleep_cmd();
+        idle.exec = "sh".to_string();
+        idle.args = vec!["-c".to_string(), format!("echo {{PREV_R}} >> {}; sleep 10", out.display())];
+        let hooks = Hooks { onstop: None, onerror: None, idle: Some(idle), reload: None, tlv: None };
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StopExec, written(&out)),
+                        (Msg::StartExec(44100), running(44100))];
+        let (data, states) = run_hooked(exec_cfg(0, 0), hooks, vec![sleep_cmd()], msgs);
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert_eq!(written, "48000\n");
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped, ExecState::Running(44100),
+                                ExecState::Stopped]);
+        assert!(data.idle.is_none());
+    }
+
+    #[test]
+    fn pid_file_follows_running_exec() {
+        let path = env::temp_dir().join(format!("gaudio_ctl_pid_{}", std::process::id()));
+        let mut cfg = exec_cfg(0, 0);
+        cfg.pid_file = Some(path.to_string_lossy().into_owned());
+        let exec = TestExec::spawn(cfg, Hooks::default(), None, vec![sleep_cmd()]);
+        exec.send(Msg::StartExec(48000), running(48000));
+        exec.wait(written(&path));
+        let pid: u32 = fs::read_to_string(&path).unwrap().trim().parse().unwrap();
+        exec.send(Msg::StopExec, State(ExecState::Stopped));
+        let (data, _) = exec.quit();
+        assert!(pid > 0);
+        assert!(!path.exists());
+        assert!(data.state.pids().is_empty());
+    }
+
+    #[test]
+    fn keepalive_survives_same_rate_blip() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.keepalive = 100;
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StopExec, Ms(20)), (Msg::StartExec(48000), Ms(0)),
+                        (Msg::StopExec, Ms(20)), (Msg::StartExec(44100), running(44100))];
+        let (data, states) = run(cfg, msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Running(44100), ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.resumes, data.stats.restarts), (2, 1, 1));
+    }
+
+    #[test]
+    fn stop_grace_elapses() {
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::StopExec, State(ExecState::Stopped))];
+        let (data, states) = run(exec_cfg(0, 50), msgs);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!(data.rate, 0);
+    }
+
+    #[test]
+    fn restart_respawns_at_current_rate() {
+        let (data, _) = run(exec_cfg(0, 0), vec![(Msg::StartExec(48000), running(48000)), (Msg::Restart, Ms(0))]);
+        assert_eq!((data.stats.starts, data.stats.restarts), (2, 1));
+        assert_eq!(data.rate, 48000);
+    }
+
+    #[test]
+    fn restart_when_stopped_is_ignored() {
+        let (data, states) = run(exec_cfg(0, 0), vec![(Msg::Restart, Ms(0))]);
+        assert!(states.is_empty());
+        assert_eq!(data.stats.starts, 0);
+    }
+
+    #[test]
+    fn takeover_adopts_matching_exec() {
+        let dir = env::temp_dir().join(format!("gaudio_ctl_takeover_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        takeover::save(&dir.join("Test.state"), 48000, &[child.id()]).unwrap();
+        let mut cfg = exec_cfg(0, 0);
+        cfg.takeover_dir = Some(dir.to_string_lossy().into_owned());
+        let (data, states) = run(cfg, vec![(Msg::StartExec(48000), Ms(0))]);
+        // adopted, not spawned, and left running on quit
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 0);
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flush_ends_debouncing_with_start() {
+        let msgs = vec![(Msg::StartExec(48000), Ms(0)), (Msg::Flush, running(48000))];
+        let (data, states) = run(exec_cfg(10000, 0), msgs);
+        assert_eq!(states, vec![ExecState::Debouncing, ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 1);
+    }
+
+    #[test]
+    fn rate_alias_falls_back_to_rate() {
+        let mut cmd = sleep_cmd();
+        cmd.args = vec!["{RALIAS}".to_string(), "-r{R}".to_string()];
+        cmd.spawn.rate_aliases.insert(352800, "DSD64".to_string());
+        assert_eq!(cmd.cmdline(352800), vec!["sleep", "DSD64", "-r352800"]);
+        assert_eq!(cmd.cmdline(48000), vec!["sleep", "48000", "-r48000"]);
+    }
+
+    #[test]
+    fn prev_rate_only_in_stop_cmdline() {
+        let mut cmd = sleep_cmd();
+        cmd.args = vec!["{PREV_R}".to_string()];
+        assert_eq!(cmd.cmdline_with(48000, &stop_values(&cmd, 48000)), vec!["sleep", "48000"]);
+        assert_eq!(cmd.cmdline(48000), vec!["sleep", "{PREV_R}"]);
+    }
+
+    #[test]
+    fn card_placeholders_substituted() {
+        let mut cmd = sleep_cmd();
+        cmd.spawn.device = 1;
+        cmd.args = vec!["hw:{CARD},{DEVICE},{SUBDEVICE}".to_string()];
+        assert_eq!(cmd.cmdline(48000), vec!["sleep", "hw:UAC2Gadget,1,0"]);
+    }
+
+    #[test]
+    fn placeholder_execs_recognized() {
+        let cmd = sleep_cmd();
+        assert!(cmd.spawn.has_placeholder("/opt/{CARD}/run"));
+        assert!(cmd.spawn.has_placeholder("loop-{RALIAS}"));
+        assert!(cmd.spawn.has_placeholder("sink{R}"));
+        assert!(!cmd.spawn.has_placeholder("alsaloop"));
+    }
+
+    #[test]
+    fn shell_values_quoted() {
+        let mut cmd = sleep_cmd().in_shell(true);
+        cmd.exec = "sh".to_string();
+        cmd.spawn.card = "my card; rm x".to_string();
+        cmd.args = vec!["-c".to_string(), "echo {CARD} {R} {ERR}".to_string()];
+        assert_eq!(cmd.cmdline_with(48000, &[(ERROR_TOKEN, "it's".to_string())]),
+                   vec!["sh", "-c", "echo 'my card; rm x' '48000' 'it'\\''s'"]);
+    }
+
+    #[test]
+    fn rate_scale_applies_to_rate_token_only() {
+        let mut cmd = sleep_cmd();
+        cmd.args = vec!["-r{R}".to_string(), "{RALIAS}".to_string()];
+        cmd.spawn.rate_scale = "3/2".parse().unwrap();
+        assert_eq!(cmd.cmdline(48000), vec!["sleep", "-r72000", "48000"]);
+        assert_eq!(cmd.spawn.rate_scale.apply(44101), None);
+        assert!("0".parse::<RateScale>().is_err());
+    }
+
+    #[test]
+    fn changed_binary_restarts_exec() {
+        let bin = env::temp_dir().join(format!("gaudio_ctl_watch_{}", std::process::id()));
+        fs::copy(resolve_exec("sleep").unwrap(), &bin).unwrap();
+        let mut cfg = exec_cfg(0, 0);
+        cfg.watch_binary = true;
+        let mut cmd = sleep_cmd();
+        cmd.exec = bin.to_string_lossy().into_owned();
+        let exec = TestExec::spawn(cfg, Hooks::default(), None, vec![cmd]);
+        exec.send(Msg::StartExec(48000), running(48000));
+        // replaced like by a package upgrade
+        let new_bin = bin.with_extension("new");
+        fs::copy(resolve_exec("sleep").unwrap(), &new_bin).unwrap();
+        fs::File::open(&new_bin).unwrap().set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        fs::rename(&new_bin, &bin).unwrap();
+        let state = exec.state.clone();
+        exec.wait(Wait::Until(Box::new(move || state.counters.restarts.load(Ordering::Relaxed) == 1)));
+        let (data, _) = exec.quit();
+        fs::remove_file(&bin).unwrap();
+        assert_eq!((data.stats.starts, data.stats.restarts), (2, 1));
+    }
+
+    #[test]
+    fn gate_defers_start_until_opened() {
+        let gate = env::temp_dir().join(format!("gaudio_ctl_gate_{}", std::process::id()));
+        let mut cfg = exec_cfg(0, 0);
+        cfg.gate_file = Some(gate.to_string_lossy().into_owned());
+        let exec = TestExec::spawn(cfg, Hooks::default(), None, vec![sleep_cmd()]);
+        // nothing started while closed
+        exec.send(Msg::StartExec(48000), Ms(200));
+        assert!(exec.progress.is_empty());
+        fs::write(&gate, "").unwrap();
+        exec.wait(running(48000));
+        let (data, states) = exec.quit();
+        fs::remove_file(&gate).unwrap();
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 1);
+    }
+
+    #[test]
+    fn confirm_reports_running_alive_exec() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.confirm = 50;
+        let (data, states) = run(cfg, vec![(Msg::StartExec(48000), running(48000))]);
+        assert_eq!(states, vec![ExecState::Starting(48000), ExecState::Running(48000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 1);
+    }
+
+    #[test]
+    fn confirm_fails_start_of_dead_exec() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.confirm = 100;
+        let mut cmd = sleep_cmd();
+        cmd.args = vec!["0".to_string()];
+        let (data, states) = run_cmds(cfg, vec![cmd], vec![(Msg::StartExec(48000), State(ExecState::Stopped))]);
+        assert_eq!(states, vec![ExecState::Starting(48000), ExecState::Stopped]);
+        assert_eq!(data.stats.starts, 0);
+    }
+
+    #[test]
+    fn message_ends_confirm_wait() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.confirm = 5000;
+        let started = Instant::now();
+        let msgs = vec![(Msg::StartExec(48000), Ms(50)), (Msg::StartExec(44100), State(ExecState::Starting(44100)))];
+        let (data, states) = run(cfg, msgs);
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+        assert_eq!(&states[..3], &[ExecState::Starting(48000), ExecState::Running(48000), ExecState::Starting(44100)]);
+        assert_eq!((data.stats.starts, data.stats.restarts), (2, 1));
+    }
+
+    #[test]
+    fn failed_start_runs_onerror() {
+        let out = env::temp_dir().join(format!("gaudio_ctl_onerror_{}", std::process::id()));
+        let mut onerror = sleep_cmd();
+        onerror.exec = "sh".to_string();
+        onerror.args = vec!["-c".to_string(), format!("echo '{{R}} {{ERR}}' > {}", out.display())];
+        let hooks = Hooks { onstop: None, onerror: Some(onerror), idle: None, reload: None, tlv: None };
+        let mut cmd = sleep_cmd();
+        cmd.exec = "/nonexistent/gaudio_ctl_exec".to_string();
+        let (data, states) = run_hooked(exec_cfg(0, 0), hooks, vec![cmd], vec![(Msg::StartExec(48000), written(&out))]);
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert!(written.starts_with("48000 "), "{}", written);
+        assert!(written.contains("/nonexistent/gaudio_ctl_exec"), "{}", written);
+        assert!(states.is_empty());
+        assert_eq!(data.stats.starts, 0);
+    }
+
+    #[test]
+    fn tlv_change_runs_tlv_hook() {
+        let out = env::temp_dir().join(format!("gaudio_ctl_tlv_{}", std::process::id()));
+        let mut tlv = sleep_cmd().in_shell(true);
+        tlv.exec = "sh".to_string();
+        tlv.args = vec!["-c".to_string(), format!("printf '%s|%s' {{DIR}} {{TLV}} > {}", out.display())];
+        let hooks = Hooks { onstop: None, onerror: None, idle: None, reload: None, tlv: Some(tlv) };
+        let msgs = vec![(Msg::Tlv("0x00000001, 0x0000ffff".to_string()), written(&out))];
+        let (_, states) = run_hooked(exec_cfg(0, 0), hooks, vec![sleep_cmd()], msgs);
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert_eq!(written, "Test|0x00000001, 0x0000ffff");
+        assert!(states.is_empty());
+    }
+
+    #[test]
+    fn failed_builtin_loop_runs_onerror() {
+        let out = env::temp_dir().join(format!("gaudio_ctl_loop_onerror_{}", std::process::id()));
+        let mut onerror = sleep_cmd();
+        onerror.exec = "sh".to_string();
+        onerror.args = vec!["-c".to_string(), format!("echo '{{R}} {{ERR}}' > {}", out.display())];
+        let hooks = Hooks { onstop: None, onerror: Some(onerror), idle: None, reload: None, tlv: None };
+        let builtin = LoopCfg {
+            capture_dev: "gaudio_ctl_missing".to_string(),
+            playback_dev: "gaudio_ctl_missing".to_string(),
+            format: LoopFormat::S16Le,
+            channels: 2,
+            period_frames: 256,
+            dry_run: false,
+        };
+        let msgs = vec![(Msg::StartExec(48000), written(&out))];
+        let (data, states) = run_builtin(exec_cfg(0, 0), hooks, Some(builtin), vec![], msgs);
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert!(written.starts_with("48000 Cannot open PCM gaudio_ctl_missing"), "{}", written);
+        assert!(states.is_empty());
+        assert_eq!(data.stats.starts, 0);
+        assert_eq!(data.state.counters.spawn_failures.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn reload_restarts_only_changed_cmds() {
+        let update = |args: &[&str]| {
+            let mut cmd = sleep_cmd();
+            cmd.args = args.iter().map(|arg| arg.to_string()).collect();
+            Box::new(ExecUpdate { cfg: exec_cfg(20, 0), cmds: vec![cmd], hooks: Hooks::default() })
+        };
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::Reload(update(&["10"])), Ms(0))];
+        let (data, _) = run(exec_cfg(0, 0), msgs);
+        assert_eq!((data.stats.starts, data.stats.restarts, data.cfg.timeout), (1, 0, 20));
+        let msgs = vec![(Msg::StartExec(48000), running(48000)), (Msg::Reload(update(&["20"])), Ms(0))];
+        let (data, _) = run(exec_cfg(0, 0), msgs);
+        assert_eq!((data.stats.starts, data.stats.restarts), (2, 1));
+    }
+
+    #[test]
+    fn exited_exec_restarts_up_to_max() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.restart_on_exit = true;
+        cfg.max_restarts = 2;
+        let mut cmd = sleep_cmd();
+        cmd.args = vec!["0.05".to_string()];
+        let (data, states) = run_cmds(cfg, vec![cmd], vec![(Msg::StartExec(48000), State(ExecState::Failed))]);
+        assert_eq!(states, vec![ExecState::Running(48000), ExecState::Stopped, ExecState::Running(48000), ExecState::Stopped,
+                                ExecState::Running(48000), ExecState::Failed, ExecState::Stopped]);
+        assert_eq!((data.stats.starts, data.stats.restarts, data.rate), (3, 2, 0));
+        assert_eq!(data.state.counters.exit_giveups.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn busy_window_sums_waits() {
+        let mut cfg = exec_cfg(100, 200);
+        cfg.keepalive = 300;
+        cfg.confirm = 50;
+        cfg.hook_timeout = 0;
+        assert_eq!(cfg.busy_window(), None);
+        cfg.hook_timeout = 10;
+        assert_eq!(cfg.busy_window(), Some(100 + 300 + 3 * 10 + 2 * 1000 + 50));
+    }
+
+    #[test]
+    fn executor_quits_within_window() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.hook_timeout = 100;
+        assert_eq!(cfg.quit_window(), 3100);
+        let update = ExecUpdate { cfg, cmds: vec![sleep_cmd()], hooks: Hooks::default() };
+        let mut executor = Executor::spawn("Test", update, None).unwrap();
+        let states = executor.state().watch();
+        executor.send(Msg::StartExec(48000)).unwrap();
+        assert_eq!(states.recv_timeout(TEST_DEADLINE), Ok(ExecState::Running(48000)));
+        let started = Instant::now();
+        executor.quit().unwrap();
+        assert!(started.elapsed() < Duration::from_millis(3100));
+        assert_eq!(executor.state().get(), ExecState::Stopped);
+    }
+
+    #[test]
+    fn stable_run_resets_restart_count() {
+        let marker = env::temp_dir().join(format!("gaudio_ctl_stable_{}", std::process::id()));
+        let mut cfg = exec_cfg(0, 0);
+        cfg.restart_on_exit = true;
+        cfg.max_restarts = 1;
+        let mut cmd = sleep_cmd();
+        cmd.exec = "sh".to_string();
+        // crashing at once, then after running longer than the max delay of 50ms and the check margin
+        cmd.args = vec!["-c".to_string(), format!("[ -e {0} ] && sleep 1; touch {0}; exit 1", marker.display())];
+        let exec = TestExec::spawn(cfg, Hooks::default(), None, vec![cmd]);
+        exec.send(Msg::StartExec(48000), running(48000));
+        let state = exec.state.clone();
+        exec.wait(Wait::Until(Box::new(move || state.counters.restarts.load(Ordering::Relaxed) == 2)));
+        let (data, states) = exec.quit();
+        fs::remove_file(&marker).unwrap();
+        assert!(!states.contains(&ExecState::Failed), "{:?}", states);
+        assert_eq!(data.state.counters.exit_giveups.load(Ordering::Relaxed), 0);
+        assert_eq!(data.exit_restarts, 1);
+    }
+
+    #[test]
+    fn exec_death_runs_onerror() {
+        let out = env::temp_dir().join(format!("gaudio_ctl_death_{}", std::process::id()));
+        let mut onerror = sleep_cmd();
+        onerror.exec = "sh".to_string();
+        onerror.args = vec!["-c".to_string(), format!("echo '{{R}} {{ERR}}' >> {}", out.display())];
+        let hooks = Hooks { onstop: None, onerror: Some(onerror), idle: None, reload: None, tlv: None };
+        let mut cfg = exec_cfg(0, 0);
+        cfg.restart_on_exit = true;
+        cfg.max_restarts = 1;
+        let mut cmd = sleep_cmd();
+        cmd.args = vec!["0.05".to_string()];
+        run_hooked(cfg, hooks, vec![cmd], vec![(Msg::StartExec(48000), State(ExecState::Failed))]);
+        let written = fs::read_to_string(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert_eq!(written, "48000 exit status: 0\n48000 exit status: 0\n");
+    }
+
+    #[test]
+    fn stop_signal_escalates_to_sigkill() {
+        let mut cfg = exec_cfg(0, 0);
+        cfg.kill_timeout = 300;
+        let spawn = |script: &str| ExecChild::Spawned(Command::new("sh").args(["-c", script]).process_group(0).spawn().unwrap());
+        let mut children = vec![spawn("sleep 10"), spawn("trap '' TERM; sleep 10")];
+        let trapping = match &children[1] {
+            ExecChild::Spawned(child) => child.id(),
+            _ => unreachable!(),
+        };
+        // letting the shell set up the trap
+        wait_until(|| ignores_term(trapping));
+        let started = Instant::now();
+        kill_children(&mut children, &cfg).unwrap();
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_secs(5), "{:?}", elapsed);
+        for child in children.iter_mut() {
+            if let ExecChild::Spawned(child) = child {
+                assert!(child.try_wait().unwrap().is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn kill_reaches_grandchildren() {
+        let path = env::temp_dir().join(format!("gaudio_ctl_group_{}", std::process::id()));
+        let mut cmd = sleep_cmd();
+        cmd.exec = "sh".to_string();
+        cmd.args = vec!["-c".to_string(), format!("sleep 10 & echo $! > {}; wait", path.display())];
+        let mut children = vec![start_child(&cmd, 48000, &[], "Test").unwrap()];
+        wait_until(file_written(&path));
+        let grandchild = fs::read_to_string(&path).unwrap().trim().to_string();
+        fs::remove_file(&path).unwrap();
+        kill_children(&mut children, &exec_cfg(0, 0)).unwrap();
+        // gone or a zombie waiting for its new parent
+        wait_until(|| {
+            let stat = fs::read_to_string(format!("/proc/{}/stat", grandchild)).unwrap_or_default();
+            stat.is_empty() || stat.contains(") Z ")
+        });
+    }
+
+    #[test]
+    fn latency_histogram_counts_buckets() {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_millis(10));
+        histogram.record(Duration::from_millis(300));
+        histogram.record(Duration::from_secs(60));
+        let counts: Vec<usize> = histogram.buckets().iter().map(|(_, count)| *count).collect();
+        assert_eq!(counts, vec![2, 0, 0, 0, 1, 0, 0, 0, 1]);
+        assert!(histogram.format().ends_with(">5000ms: 1"));
+    }
+
+    #[test]
+    fn rate_group_prefers_overrides() {
+        let echo = CmdCfg::new("echo".to_string(), Vec::new(), sleep_cmd().spawn);
+        let cmds = vec![sleep_cmd(), echo.for_rates(vec![(44100, 44100), (352800, 384000)])];
+        let execs = |rate| rate_group(&cmds, rate).iter().map(|cmd| cmd.exec.clone()).collect::<Vec<String>>();
+        assert_eq!(execs(48000), vec!["sleep"]);
+        assert_eq!(execs(44100), vec!["echo"]);
+        assert_eq!(execs(384000), vec!["echo"]);
+    }
+}
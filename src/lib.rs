@@ -0,0 +1,120 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use anyhow::Result;
+use clap::ArgEnum;
+use crossbeam_channel::Sender;
+use log::{debug, info};
+
+use executor::{CmdCfg, ExecUpdate, SharedState};
+
+pub use executor::Executor;
+pub use watcher::Watcher;
+
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod backoff;
+pub mod builtin_loop;
+pub mod camilla;
+pub mod cmdline;
+pub mod coupling;
+pub mod ctl_enum;
+pub mod ctl_list;
+pub mod ctl_lock;
+pub mod ctl_tlv;
+pub mod error;
+pub mod event_trace;
+pub mod logging;
+pub mod executor;
+pub mod json;
+pub mod metrics;
+pub mod privileges;
+pub mod signals;
+pub mod socket;
+pub mod takeover;
+pub mod watchdog;
+pub mod watcher;
+
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Capture,
+    Playback,
+}
+
+// messages sent to exec threads
+pub enum Msg {
+    // stop exec
+    StopExec,
+    // start with rate
+    StartExec(usize),
+    // restart the running exec at the current rate
+    Restart,
+    // end pending debouncing/stop grace now, proceeding with the start/stop
+    Flush,
+    // new config, applied once nothing is pending
+    Reload(Box<ExecUpdate>),
+    // TLV of the rate ctl changed, the words in hex
+    Tlv(String),
+    // stop the thread
+    Quit,
+}
+
+// direction data available to the control interfaces
+#[derive(Clone)]
+pub struct DirHandle {
+    pub dir: String,
+    pub state: Arc<SharedState>,
+    pub sender: Sender<Msg>,
+    // disabled direction ignores rate changes
+    pub enabled: Arc<AtomicBool>,
+    // last rate reported by the ctl, also while disabled
+    pub last_rate: Arc<AtomicUsize>,
+    pub cmds: Arc<Mutex<Vec<CmdCfg>>>,
+    // rate injected by the control socket, the next ctl rate is dispatched even if unchanged
+    pub simulated: Arc<AtomicBool>,
+}
+
+impl DirHandle {
+    // commands as they would be started at the last reported rate
+    pub fn resolved(&self) -> (usize, Vec<Vec<String>>) {
+        let rate = self.last_rate.load(Ordering::SeqCst);
+        (rate, executor::rate_group(&self.cmds.lock().unwrap(), rate).iter().map(|cmd| cmd.cmdline(rate)).collect())
+    }
+
+    pub fn set_enabled(&self, enabled: bool) -> Result<()> {
+        if self.enabled.swap(enabled, Ordering::SeqCst) == enabled {
+            return Ok(());
+        }
+        info!("{}: {}", self.dir, if enabled { "Enabled" } else { "Disabled" });
+        if enabled {
+            // resuming at the current rate
+            let rate = self.last_rate.load(Ordering::SeqCst);
+            if rate > 0 {
+                self.sender.send(Msg::StartExec(rate))?;
+            }
+        } else {
+            self.sender.send(Msg::StopExec)?;
+        }
+        Ok(())
+    }
+
+    // synthetic rate handled like a ctl event, actually running the commands
+    pub fn simulate(&self, rate: usize) -> Result<()> {
+        if signals::paused() {
+            info!("{}: Paused, ignoring simulated rate {}", self.dir, rate);
+            return Ok(());
+        }
+        info!("{}: Simulated rate {}", self.dir, rate);
+        self.last_rate.store(rate, Ordering::SeqCst);
+        self.simulated.store(true, Ordering::SeqCst);
+        if !self.enabled.load(Ordering::SeqCst) {
+            debug!("{}: Disabled, ignoring simulated rate", self.dir);
+            return Ok(());
+        }
+        match rate {
+            0 => self.sender.send(Msg::StopExec)?,
+            rate => self.sender.send(Msg::StartExec(rate))?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+use std::thread;
+
+use env_logger::Logger;
+use log::{LevelFilter, Log, Metadata, Record};
+
+// level overridden for threads of a direction, named by the direction (e.g. "Playback Thread")
+pub struct DirLevelLogger {
+    inner: Logger,
+    default_level: LevelFilter,
+    // thread name prefix and its level
+    dir_levels: Vec<(String, LevelFilter)>,
+    // log target and its level, overriding the thread level
+    target_levels: Vec<(String, LevelFilter)>,
+}
+
+impl DirLevelLogger {
+    pub fn new(inner: Logger, default_level: LevelFilter, dir_levels: Vec<(String, LevelFilter)>,
+               target_levels: Vec<(String, LevelFilter)>) -> Self {
+        DirLevelLogger { inner, default_level, dir_levels, target_levels }
+    }
+
+    fn thread_level(&self) -> LevelFilter {
+        let current = thread::current();
+        let name = current.name().unwrap_or_default();
+        self.dir_levels.iter()
+            .find(|(dir, _)| name.starts_with(dir.as_str()))
+            .map_or(self.default_level, |(_, level)| *level)
+    }
+}
+
+impl Log for DirLevelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let target_level = self.target_levels.iter().find(|(target, _)| target == metadata.target());
+        metadata.level() <= target_level.map_or_else(|| self.thread_level(), |(_, level)| *level)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// TARGET=LEVEL entries of the RUST_LOG syntax, e.g. decision=trace, other entries ignored
+pub fn parse_target_levels(spec: &str) -> Vec<(String, LevelFilter)> {
+    spec.split(',')
+        .filter_map(|entry| entry.split_once('='))
+        .filter_map(|(target, level)| level.trim().parse().ok().map(|level| (target.trim().to_string(), level)))
+        .collect()
+}
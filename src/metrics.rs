@@ -0,0 +1,165 @@
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::DirHandle;
+use crate::executor::{Counters, ExecState};
+
+// a stalled client must not block the serving thread for long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// minimal HTTP server of the Prometheus text format, GET /metrics only
+pub fn start_metrics_thread(addr: &str, dirs: Vec<DirHandle>) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    debug!("Serving metrics on http://{}/metrics", addr);
+    thread::Builder::new()
+        .name("Metrics Thread".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(err) = handle_client(stream, &dirs) {
+                            warn!("Metrics client failed, error: {}", err);
+                        }
+                    }
+                    Err(err) => warn!("Metrics accept failed, error: {}", err),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, dirs: &[DirHandle]) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // headers ignored, up to the empty line
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 || header == "\r\n" || header == "\n" {
+            break;
+        }
+    }
+    let (status, content_type, body) = match request.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["GET", "/metrics", ..] => ("200 OK", "text/plain; version=0.0.4", format_metrics(dirs)),
+        ["GET", ..] => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+    write!(writer, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, content_type, body.len(), body)?;
+    Ok(())
+}
+
+// counter name and help, in the order of counter_values
+const COUNTERS: [(&str, &str); 7] = [
+    ("starts", "Exec starts"),
+    ("stops", "Exec stops"),
+    ("restarts", "Exec restarts"),
+    ("spawn_failures", "Failed exec starts"),
+    ("debounce_cancels", "Debounced starts ended with a stop"),
+    ("reloads", "Rate changes handled by reloading the running exec"),
+    ("exit_giveups", "Exited execs not restarted anymore, max restarts reached"),
+];
+
+fn counter_values(counters: &Counters) -> [usize; 7] {
+    [&counters.starts, &counters.stops, &counters.restarts, &counters.spawn_failures, &counters.debounce_cancels,
+     &counters.reloads, &counters.exit_giveups]
+        .map(|counter| counter.load(Ordering::Relaxed))
+}
+
+// counters of the direction on one line, e.g. for the final snapshot logged on quit
+pub fn format_counters(dir: &DirHandle) -> String {
+    COUNTERS.iter()
+        .zip(counter_values(&dir.state.counters))
+        .map(|((name, _), value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn format_metrics(dirs: &[DirHandle]) -> String {
+    let mut out = String::new();
+    for (idx, (name, help)) in COUNTERS.iter().enumerate() {
+        let _ = writeln!(out, "# HELP gaudio_ctl_{}_total {}", name, help);
+        let _ = writeln!(out, "# TYPE gaudio_ctl_{}_total counter", name);
+        for d in dirs {
+            let _ = writeln!(out, "gaudio_ctl_{}_total{{dir=\"{}\"}} {}", name, d.dir, counter_values(&d.state.counters)[idx]);
+        }
+    }
+    let _ = writeln!(out, "# HELP gaudio_ctl_rate Rate of the running exec, 0 = stopped");
+    let _ = writeln!(out, "# TYPE gaudio_ctl_rate gauge");
+    for d in dirs {
+        let rate = match d.state.get() {
+            ExecState::Running(rate) | ExecState::Starting(rate) => rate,
+            _ => 0,
+        };
+        let _ = writeln!(out, "gaudio_ctl_rate{{dir=\"{}\"}} {}", d.dir, rate);
+    }
+    let _ = writeln!(out, "# HELP gaudio_ctl_start_latency_seconds Time from receiving the rate to spawning");
+    let _ = writeln!(out, "# TYPE gaudio_ctl_start_latency_seconds histogram");
+    for d in dirs {
+        // cumulative counts of the buckets
+        let mut total = 0;
+        for (bound, count) in d.state.latency.buckets() {
+            total += count;
+            let le = bound.map_or("+Inf".to_string(), |bound| (bound as f64 / 1000.0).to_string());
+            let _ = writeln!(out, "gaudio_ctl_start_latency_seconds_bucket{{dir=\"{}\",le=\"{}\"}} {}", d.dir, le, total);
+        }
+        let _ = writeln!(out, "gaudio_ctl_start_latency_seconds_sum{{dir=\"{}\"}} {}", d.dir,
+                         d.state.latency.sum_ms() as f64 / 1000.0);
+        let _ = writeln!(out, "gaudio_ctl_start_latency_seconds_count{{dir=\"{}\"}} {}", d.dir, total);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    use crossbeam_channel::unbounded;
+
+    use crate::executor::SharedState;
+
+    use super::*;
+
+    #[test]
+    fn counters_per_direction() {
+        let state = Arc::new(SharedState::default());
+        state.counters.starts.fetch_add(2, Ordering::Relaxed);
+        let (sender, _recv) = unbounded();
+        let handle = DirHandle {
+            dir: "Playback".to_string(),
+            state,
+            sender,
+            enabled: Arc::new(AtomicBool::new(true)),
+            last_rate: Arc::new(AtomicUsize::new(0)),
+            cmds: Arc::new(Mutex::new(Vec::new())),
+            simulated: Arc::new(AtomicBool::new(false)),
+        };
+        let metrics = format_metrics(std::slice::from_ref(&handle));
+        assert!(metrics.contains("gaudio_ctl_starts_total{dir=\"Playback\"} 2\n"));
+        assert!(metrics.contains("gaudio_ctl_rate{dir=\"Playback\"} 0\n"));
+        assert!(metrics.contains("gaudio_ctl_start_latency_seconds_bucket{dir=\"Playback\",le=\"+Inf\"} 0\n"));
+        assert_eq!(format_counters(&handle),
+                   "starts=2 stops=0 restarts=0 spawn_failures=0 debounce_cancels=0 reloads=0 exit_giveups=0");
+    }
+
+    #[test]
+    fn stalled_client_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // connected, never sending the request
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert!(handle_client(stream, &[]).is_err());
+    }
+}
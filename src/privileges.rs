@@ -0,0 +1,32 @@
+use std::ffi::CString;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use nix::unistd::{Gid, Group, initgroups, setgid, setuid, User};
+
+// switching to the user/group after the privileged setup, all threads included
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> Result<()> {
+    let user = user.map(|name| {
+        User::from_name(name)?.ok_or_else(|| anyhow!("Unknown user {}", name))
+    }).transpose()?;
+    let gid = match group {
+        Some(name) => Some(Group::from_name(name)?.ok_or_else(|| anyhow!("Unknown group {}", name))?.gid),
+        // primary group of the user
+        None => user.as_ref().map(|user| user.gid),
+    };
+    // group first, setgid is not permitted after dropping the root user
+    if let Some(gid) = gid {
+        if let Some(user) = &user {
+            initgroups(&CString::new(user.name.as_str())?, gid)
+                .with_context(|| format!("Cannot set supplementary groups of user {}", user.name))?;
+        }
+        setgid(gid).with_context(|| format!("Cannot switch to group {}", gid))?;
+    }
+    if let Some(user) = &user {
+        setuid(user.uid).with_context(|| format!("Cannot switch to user {}", user.name))?;
+    }
+    info!("Dropped privileges to user {}, group {}",
+          user.as_ref().map_or("(unchanged)".to_string(), |user| user.name.clone()),
+          gid.map_or("(unchanged)".to_string(), |gid: Gid| gid.to_string()));
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use alsa::poll::{Flags, pollfd};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use nix::sys::signal::{SigSet, Signal};
+use nix::unistd::{pipe, read, write};
+
+// rate changes ignored, running execs left untouched
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+// self-pipe waking the main loop from the signal thread
+static WAKE_READ: AtomicI32 = AtomicI32::new(-1);
+static WAKE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+// reason of waking the main loop, sent as one byte over the self-pipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    Shutdown,
+    // re-reading the config
+    Reload,
+}
+
+impl Wake {
+    fn code(self) -> u8 {
+        match self {
+            Wake::Shutdown => 1,
+            Wake::Reload => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Wake::Shutdown),
+            2 => Some(Wake::Reload),
+            _ => None,
+        }
+    }
+}
+
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+// must be called before spawning other threads, they inherit the blocked mask
+pub fn start_signal_thread() -> Result<()> {
+    let (wake_read, wake_write) = pipe()?;
+    WAKE_READ.store(wake_read, Ordering::SeqCst);
+    WAKE_WRITE.store(wake_write, Ordering::SeqCst);
+    let mut set = SigSet::empty();
+    set.add(Signal::SIGUSR2);
+    set.add(Signal::SIGINT);
+    set.add(Signal::SIGTERM);
+    set.add(Signal::SIGHUP);
+    set.thread_block()?;
+    thread::Builder::new()
+        .name("Signal Thread".to_string())
+        .spawn(move || loop {
+            match set.wait() {
+                Ok(Signal::SIGUSR2) => {
+                    let paused = !PAUSED.fetch_xor(true, Ordering::SeqCst);
+                    info!("{}", if paused { "Paused, ignoring rate changes" } else { "Resumed" });
+                }
+                Ok(signal @ (Signal::SIGINT | Signal::SIGTERM)) => {
+                    info!("Received {}, shutting down", signal);
+                    send_wake(Wake::Shutdown);
+                }
+                Ok(Signal::SIGHUP) => {
+                    info!("Received SIGHUP, reloading config");
+                    send_wake(Wake::Reload);
+                }
+                Ok(signal) => warn!("Unexpected signal {}", signal),
+                Err(err) => warn!("Waiting for signals failed, error: {}", err),
+            }
+        })?;
+    Ok(())
+}
+
+pub fn send_wake(wake: Wake) {
+    if let Err(err) = write(WAKE_WRITE.load(Ordering::SeqCst), &[wake.code()]) {
+        warn!("Cannot wake the main loop, error: {}", err);
+    }
+}
+
+// read end of the self-pipe, polled along with the ctl
+pub fn wake_pollfd() -> pollfd {
+    pollfd { fd: WAKE_READ.load(Ordering::SeqCst) as RawFd, events: Flags::IN.bits(), revents: 0 }
+}
+
+// to be called only after poll reported the self-pipe readable
+pub fn read_wake() -> Result<Wake> {
+    let mut buf = [0u8; 1];
+    read(WAKE_READ.load(Ordering::SeqCst), &mut buf)?;
+    Wake::from_code(buf[0]).ok_or_else(|| anyhow!("Unknown wake-up code {}", buf[0]))
+}
+
+// sleeping for the whole timeout, true = shutdown requested meanwhile
+pub fn shutdown_within(timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match wait_wake(left)? {
+            Some(Wake::Shutdown) => return Ok(true),
+            Some(Wake::Reload) => info!("Reload ignored while waiting"),
+            None => return Ok(false),
+        }
+    }
+}
+
+// sleeping for the timeout unless woken earlier
+pub fn wait_wake(timeout: Duration) -> Result<Option<Wake>> {
+    let mut fds = [wake_pollfd()];
+    match alsa::poll::poll(&mut fds, timeout.as_millis() as i32)? {
+        0 => Ok(None),
+        _ => read_wake().map(Some),
+    }
+}
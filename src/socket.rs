@@ -0,0 +1,324 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+
+use crate::executor::ExecState;
+use crate::signals::{self, Wake};
+use crate::{DirHandle, Msg};
+
+// an idle or stalled client must not block the serving thread for long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// socket file of the listener, removed when dropped on exit
+pub struct ControlSocket {
+    path: String,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        // e.g. a root-owned dir after dropping the privileges
+        if let Err(err) = fs::remove_file(&self.path) {
+            debug!("Cannot remove control socket {}, error: {}", self.path, err);
+        }
+    }
+}
+
+pub fn start_socket_thread(path: &str, dirs: Vec<DirHandle>) -> Result<ControlSocket> {
+    remove_stale(path)?;
+    let listener = UnixListener::bind(path)?;
+    debug!("Listening on control socket {}", path);
+    thread::Builder::new()
+        .name("Socket Thread".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(err) = handle_client(stream, &dirs) {
+                            warn!("Control socket client failed, error: {}", err);
+                        }
+                    }
+                    Err(err) => warn!("Control socket accept failed, error: {}", err),
+                }
+            }
+        })?;
+    Ok(ControlSocket { path: path.to_string() })
+}
+
+// only a socket from a previous run nobody listens on is replaced
+fn remove_stale(path: &str) -> Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(anyhow!("Control socket path {} exists and is not a socket", path));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(anyhow!("Control socket {} is in use, another instance running?", path));
+    }
+    debug!("Removing stale control socket {}", path);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+fn handle_client(stream: UnixStream, dirs: &[DirHandle]) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                debug!("Control socket client idle for {} s, closing", CLIENT_TIMEOUT.as_secs());
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(reply) = reply(&line, dirs) {
+            writer.write_all(reply.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// None for an empty line
+fn reply(line: &str, dirs: &[DirHandle]) -> Option<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    debug!("Control socket command: {:?}", words);
+    let reply = match words.as_slice() {
+        [] => return None,
+        ["status"] => status(dirs),
+        ["enable", dir] => set_enabled(dirs, dir, true),
+        ["disable", dir] => set_enabled(dirs, dir, false),
+        ["flush", dir] => flush(dirs, dir),
+        ["simulate", dir, rate] => simulate(dirs, dir, rate),
+        ["start", dir, rate] => match rate.parse::<usize>() {
+            Ok(rate) if rate > 0 => force(dirs, dir, rate),
+            _ => format!("ERR invalid rate '{}'\n", rate),
+        },
+        ["stop", dir] => force(dirs, dir, 0),
+        ["quit"] => {
+            signals::send_wake(Wake::Shutdown);
+            "OK\n".to_string()
+        }
+        ["resolved", dir] => match find_dir(dirs, dir) {
+            Some(d) => format_resolved(d),
+            None => unknown_dir(dir),
+        },
+        ["latency", dir] => match find_dir(dirs, dir) {
+            Some(d) => format!("{}: {}\n", d.dir, d.state.latency.format()),
+            None => unknown_dir(dir),
+        },
+        _ => format!("ERR unknown command '{}'\n", line.trim()),
+    };
+    Some(reply)
+}
+
+fn status(dirs: &[DirHandle]) -> String {
+    dirs.iter()
+        .map(|d| {
+            let enabled = if d.enabled.load(Ordering::SeqCst) { "" } else { " (disabled)" };
+            let remaining = match (d.state.get(), d.state.debounce_remaining()) {
+                (ExecState::Debouncing, Some(remaining)) => format!(", {} ms left", remaining.as_millis()),
+                _ => String::new(),
+            };
+            let pids: Vec<String> = d.state.pids().iter().map(u32::to_string).collect();
+            let pids = match pids.is_empty() {
+                true => String::new(),
+                false => format!(", PID {}", pids.join(" ")),
+            };
+            format!("{}: {}{}{}{}\n", d.dir, d.state.get(), remaining, pids, enabled)
+        })
+        .collect()
+}
+
+fn set_enabled(dirs: &[DirHandle], dir: &str, enabled: bool) -> String {
+    match find_dir(dirs, dir) {
+        Some(d) => match d.set_enabled(enabled) {
+            Ok(_) => "OK\n".to_string(),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        None => unknown_dir(dir),
+    }
+}
+
+fn flush(dirs: &[DirHandle], dir: &str) -> String {
+    match find_dir(dirs, dir) {
+        Some(d) => match d.sender.send(Msg::Flush) {
+            Ok(_) => "OK\n".to_string(),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        None => unknown_dir(dir),
+    }
+}
+
+fn simulate(dirs: &[DirHandle], dir: &str, rate: &str) -> String {
+    let rate: usize = match rate.parse() {
+        Ok(rate) => rate,
+        Err(_) => return format!("ERR invalid rate '{}'\n", rate),
+    };
+    match find_dir(dirs, dir) {
+        Some(d) => match d.simulate(rate) {
+            Ok(_) => "OK\n".to_string(),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        None => unknown_dir(dir),
+    }
+}
+
+// simulated rate without waiting for the debouncing/stop grace
+fn force(dirs: &[DirHandle], dir: &str, rate: usize) -> String {
+    match find_dir(dirs, dir) {
+        Some(d) => match d.simulate(rate).and_then(|_| Ok(d.sender.send(Msg::Flush)?)) {
+            Ok(_) => "OK\n".to_string(),
+            Err(err) => format!("ERR {}\n", err),
+        },
+        None => unknown_dir(dir),
+    }
+}
+
+// exact argv of each command, one per line
+pub fn format_resolved(d: &DirHandle) -> String {
+    let (rate, cmdlines) = d.resolved();
+    cmdlines.iter()
+        .map(|cmdline| format!("{} (rate {}): {:?}\n", d.dir, rate, cmdline))
+        .collect()
+}
+
+// direction name is case insensitive
+fn find_dir<'a>(dirs: &'a [DirHandle], dir: &str) -> Option<&'a DirHandle> {
+    dirs.iter().find(|d| d.dir.eq_ignore_ascii_case(dir))
+}
+
+fn unknown_dir(dir: &str) -> String {
+    format!("ERR unknown direction '{}'\n", dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    use crossbeam_channel::{unbounded, Receiver};
+
+    use crate::executor::SharedState;
+
+    use super::*;
+
+    fn handle() -> (Vec<DirHandle>, Receiver<Msg>) {
+        let (sender, recv) = unbounded();
+        let handle = DirHandle {
+            dir: "Playback".to_string(),
+            state: Arc::new(SharedState::default()),
+            sender,
+            enabled: Arc::new(AtomicBool::new(true)),
+            last_rate: Arc::new(AtomicUsize::new(0)),
+            cmds: Arc::new(Mutex::new(Vec::new())),
+            simulated: Arc::new(AtomicBool::new(false)),
+        };
+        (vec![handle], recv)
+    }
+
+    fn reply_of(line: &str, dirs: &[DirHandle]) -> String {
+        reply(line, dirs).unwrap()
+    }
+
+    #[test]
+    fn status_lists_directions() {
+        let (dirs, _recv) = handle();
+        assert_eq!(reply(" ", &dirs), None);
+        assert_eq!(reply_of("status", &dirs), "Playback: Stopped\n");
+        dirs[0].enabled.store(false, Ordering::SeqCst);
+        assert_eq!(reply_of("status", &dirs), "Playback: Stopped (disabled)\n");
+    }
+
+    #[test]
+    fn start_stop_flush_send_messages() {
+        let (dirs, recv) = handle();
+        assert_eq!(reply_of("start playback 48000", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::StartExec(48000))));
+        assert!(matches!(recv.try_recv(), Ok(Msg::Flush)));
+        assert!(dirs[0].simulated.load(Ordering::SeqCst));
+        assert_eq!(reply_of("stop Playback", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::StopExec)));
+        assert!(matches!(recv.try_recv(), Ok(Msg::Flush)));
+        assert_eq!(reply_of("flush playback", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::Flush)));
+        assert_eq!(reply_of("start playback 0", &dirs), "ERR invalid rate '0'\n");
+        assert_eq!(reply_of("stop capture", &dirs), "ERR unknown direction 'capture'\n");
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn simulate_stores_rate() {
+        let (dirs, recv) = handle();
+        assert_eq!(reply_of("simulate playback 44100", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::StartExec(44100))));
+        assert_eq!(dirs[0].last_rate.load(Ordering::SeqCst), 44100);
+        assert_eq!(reply_of("simulate playback 0", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::StopExec)));
+        assert_eq!(reply_of("simulate playback fast", &dirs), "ERR invalid rate 'fast'\n");
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn enable_disable_toggle_direction() {
+        let (dirs, recv) = handle();
+        dirs[0].last_rate.store(48000, Ordering::SeqCst);
+        assert_eq!(reply_of("disable playback", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::StopExec)));
+        assert!(!dirs[0].enabled.load(Ordering::SeqCst));
+        // disabled direction ignores the simulated rate
+        assert_eq!(reply_of("simulate playback 96000", &dirs), "OK\n");
+        assert!(recv.try_recv().is_err());
+        assert_eq!(reply_of("enable playback", &dirs), "OK\n");
+        assert!(matches!(recv.try_recv(), Ok(Msg::StartExec(96000))));
+        assert_eq!(reply_of("enable playback", &dirs), "OK\n");
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn resolved_latency_quit_unknown() {
+        let (dirs, _recv) = handle();
+        assert_eq!(reply_of("resolved playback", &dirs), "");
+        assert!(reply_of("latency playback", &dirs).starts_with("Playback: <=10ms: 0, "));
+        assert_eq!(reply_of("latency capture", &dirs), "ERR unknown direction 'capture'\n");
+        // no main loop to wake in the test
+        assert_eq!(reply_of("quit", &dirs), "OK\n");
+        assert_eq!(reply_of("restart  playback", &dirs), "ERR unknown command 'restart  playback'\n");
+    }
+
+    #[test]
+    fn only_stale_socket_replaced() {
+        let dir = std::env::temp_dir().join(format!("gaudio_ctl_socket_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file");
+        fs::write(&file, "keep").unwrap();
+        let refused_file = remove_stale(file.to_str().unwrap()).is_err();
+        let path = dir.join("ctl.sock");
+        let path = path.to_str().unwrap();
+        let live = UnixListener::bind(path).unwrap();
+        let refused_live = remove_stale(path).is_err();
+        drop(live);
+        let socket = start_socket_thread(path, Vec::new()).unwrap();
+        let replaced = UnixStream::connect(path).is_ok();
+        drop(socket);
+        let removed = fs::symlink_metadata(path).is_err();
+        let kept = fs::read_to_string(&file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(refused_file);
+        assert!(refused_live);
+        assert!(replaced);
+        assert!(removed);
+        assert_eq!(kept, "keep");
+    }
+}
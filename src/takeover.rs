@@ -0,0 +1,102 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+// running exec processes of a direction, left running on quit
+pub struct TakeoverState {
+    pub rate: usize,
+    pub pids: Vec<u32>,
+}
+
+// single line: rate pid..., replaced atomically, a crash while writing must not leave a truncated state
+pub fn save(path: &Path, rate: usize, pids: &[u32]) -> Result<()> {
+    let pids: Vec<String> = pids.iter().map(u32::to_string).collect();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, format!("{} {}\n", rate, pids.join(" ")))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Option<TakeoverState>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut fields = content.split_whitespace();
+    let rate = fields.next().ok_or_else(|| anyhow!("empty state file {}", path.display()))?.parse()?;
+    let pids = fields.map(str::parse).collect::<Result<Vec<u32>, _>>()?;
+    Ok(Some(TakeoverState { rate, pids }))
+}
+
+pub fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+// the pid may have been reused by an unrelated process
+pub fn cmdline_matches(pid: u32, cmdline: &[String]) -> bool {
+    match fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(content) => {
+            let args: Vec<String> = content.split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            args == cmdline
+        }
+        Err(_) => false,
+    }
+}
+
+// not our child, cannot be waited for - polling until the process disappears
+pub fn kill_adopted(pid: u32) -> io::Result<()> {
+    match kill(Pid::from_raw(pid as i32), Signal::SIGKILL) {
+        Ok(_) => {}
+        // same as killing an already finished child
+        Err(Errno::ESRCH) => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        Err(errno) => return Err(io::Error::from_raw_os_error(errno as i32)),
+    }
+    wait_gone(pid, Instant::now() + Duration::from_secs(1));
+    Ok(())
+}
+
+// false when the process still exists at the deadline
+pub fn wait_gone(pid: u32, deadline: Instant) -> bool {
+    loop {
+        if !Path::new(&format!("/proc/{}", pid)).exists() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_state_loaded() {
+        let path = std::env::temp_dir().join(format!("gaudio_ctl_takeover_{}.state", std::process::id()));
+        save(&path, 44100, &[1]).unwrap();
+        save(&path, 48000, &[12, 34]).unwrap();
+        let state = load(&path).unwrap().unwrap();
+        let tmp_left = Path::new(&format!("{}.tmp", path.display())).exists();
+        remove(&path).unwrap();
+        assert_eq!((state.rate, state.pids), (48000, vec![12, 34]));
+        assert!(!tmp_left);
+        assert!(load(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Result};
+
+// extra ctl with its own executor, besides the capture/playback ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchSpec {
+    // executor name in logs and control interfaces, default Watch1, Watch2, ...
+    pub label: Option<String>,
+    pub name: String,
+    pub device: u32,
+    pub subdevice: u32,
+    pub fixed_rate: Option<usize>,
+    pub cmd: String,
+}
+
+impl WatchSpec {
+    // label defaults to Watch1, Watch2, ... by the position in --watch
+    pub fn label(&self, index: usize) -> String {
+        self.label.clone().unwrap_or_else(|| format!("Watch{}", index + 1))
+    }
+}
+
+// labels name the executors in the control socket and the metrics, they must be unique
+pub fn check_labels(specs: &[WatchSpec]) -> Result<()> {
+    let mut labels = vec!["Playback".to_string(), "Capture".to_string()];
+    for (index, spec) in specs.iter().enumerate() {
+        let label = spec.label(index);
+        if labels.contains(&label) {
+            return Err(anyhow!("label '{}' of watch '{}' already used", label, spec.name));
+        }
+        labels.push(label);
+    }
+    Ok(())
+}
+
+// comma separated KEY=VALUE, cmd last as it may contain commas
+pub fn parse_watch(s: &str) -> Result<WatchSpec> {
+    let (params, cmd) = match s.strip_prefix("cmd=") {
+        Some(cmd) => ("", cmd),
+        None => s.split_once(",cmd=").ok_or_else(|| anyhow!("missing cmd= in '{}'", s))?,
+    };
+    let mut spec = WatchSpec {
+        label: None,
+        name: String::new(),
+        device: 0,
+        subdevice: 0,
+        fixed_rate: None,
+        cmd: cmd.trim().to_string(),
+    };
+    for param in params.split(',').filter(|param| !param.is_empty()) {
+        let (key, value) = param.split_once('=').ok_or_else(|| anyhow!("missing '=' in '{}'", param))?;
+        match key {
+            "name" => spec.name = value.to_string(),
+            "label" => spec.label = Some(value.to_string()),
+            "device" => spec.device = value.parse()?,
+            "subdevice" => spec.subdevice = value.parse()?,
+            "fixed-rate" | "fixed_rate" => spec.fixed_rate = Some(value.parse()?),
+            _ => return Err(anyhow!("unknown key '{}' in '{}'", key, s)),
+        }
+    }
+    if spec.name.is_empty() {
+        return Err(anyhow!("missing name= in '{}'", s));
+    }
+    if spec.cmd.is_empty() {
+        return Err(anyhow!("empty cmd= in '{}'", s));
+    }
+    gaudio_ctl::cmdline::check(&spec.cmd)?;
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmd_keeps_commas() {
+        let spec = parse_watch("name=Capture Rate,device=1,label=Uac2,cmd=mycmd -r {R} -c a,b").unwrap();
+        assert_eq!(spec, WatchSpec {
+            label: Some("Uac2".to_string()),
+            name: "Capture Rate".to_string(),
+            device: 1,
+            subdevice: 0,
+            fixed_rate: None,
+            cmd: "mycmd -r {R} -c a,b".to_string(),
+        });
+        assert!(parse_watch("name=Capture Rate").is_err());
+        assert!(parse_watch("cmd=mycmd").is_err());
+        assert!(parse_watch("name=Capture Rate,bogus=1,cmd=mycmd").is_err());
+        assert!(parse_watch("name=Capture Rate,cmd=mycmd 'open").is_err());
+    }
+
+    #[test]
+    fn labels_unique() {
+        let watch = |s: &str| parse_watch(s).unwrap();
+        assert!(check_labels(&[watch("name=A,cmd=a"), watch("name=B,label=Uac2,cmd=b")]).is_ok());
+        assert!(check_labels(&[watch("name=A,label=Playback,cmd=a")]).is_err());
+        assert!(check_labels(&[watch("name=A,cmd=a"), watch("name=B,label=Watch1,cmd=b")]).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{error, info};
+
+use crate::DirHandle;
+
+// detecting exec threads stuck in handling a message, e.g. waiting for an unkillable child
+pub fn start_watchdog_thread(dirs: Vec<DirHandle>, threshold_ms: usize, abort: bool) -> Result<()> {
+    let threshold = Duration::from_millis(threshold_ms as u64);
+    thread::Builder::new()
+        .name("Watchdog Thread".to_string())
+        .spawn(move || {
+            // reporting each hang once
+            let mut stuck = vec![false; dirs.len()];
+            loop {
+                thread::sleep(threshold / 2);
+                for (handle, stuck) in dirs.iter().zip(stuck.iter_mut()) {
+                    match handle.state.busy_for() {
+                        Some(busy) if busy > threshold => {
+                            if !*stuck {
+                                error!("{}: Exec thread unresponsive for {} ms", handle.dir, busy.as_millis());
+                                *stuck = true;
+                            }
+                            if abort {
+                                error!("Exiting due to unresponsive {} exec thread", handle.dir);
+                                process::exit(1);
+                            }
+                        }
+                        _ => {
+                            if *stuck {
+                                info!("{}: Exec thread responsive again", handle.dir);
+                                *stuck = false;
+                            }
+                        }
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::time::Duration;
+
+use alsa::Ctl;
+use alsa::ctl::{ElemId, ElemIface, ElemType};
+use alsa::hctl::{Elem, HCtl};
+use alsa::poll::Descriptors;
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use nix::errno::Errno;
+use nix::unistd::{access, AccessFlags};
+
+use crate::ctl_enum;
+use crate::error::Error;
+use crate::signals::{self, Wake};
+
+pub struct CtlData<'a> {
+    // matched candidate name
+    pub name: String,
+    pub elem: Elem<'a>,
+    pub numid: u32,
+    // index of the elem id, reported in events
+    pub elem_index: u32,
+    // index of the value with the rate, for multi-value ctls
+    pub value_index: u32,
+    // rates of enumerated ctl items, None for integer ctl
+    pub enum_rates: Option<Vec<usize>>,
+    // boolean ctl type
+    pub boolean: bool,
+    pub mapping: RateMapping,
+    // ctl reporting the rate instead of this one, e.g. for another clock source
+    pub secondary: Option<Box<CtlData<'a>>>,
+}
+
+// meaning of the ctl value
+#[derive(Debug, Clone, Copy)]
+pub enum RateMapping {
+    // value = rate, 0 = stop
+    Direct,
+    // nonzero value (zero if inverted) = run at the fixed rate
+    Fixed { rate: usize, invert: bool },
+}
+
+impl RateMapping {
+    // invert requires the fixed rate, checked by clap
+    pub fn new(fixed_rate: Option<usize>, invert: bool) -> Self {
+        match fixed_rate {
+            Some(rate) => RateMapping::Fixed { rate, invert },
+            None => RateMapping::Direct,
+        }
+    }
+
+    pub fn normalize(&self, value: usize) -> usize {
+        match *self {
+            RateMapping::Direct => value,
+            RateMapping::Fixed { rate, invert } => if (value != 0) != invert { rate } else { 0 },
+        }
+    }
+}
+
+// result of waiting in the event loop
+pub enum Ready {
+    Ctl,
+    Timeout,
+    Wake(Wake),
+}
+
+// ctl event as read by the watcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CtlEvent {
+    pub numid: u32,
+    pub index: u32,
+    // raw event mask
+    pub mask: u32,
+    // value changed
+    pub value: bool,
+    // TLV (e.g. dB range) changed
+    pub tlv: bool,
+}
+
+// ctl event subscription of the card, waking also on signals
+pub struct Watcher {
+    ctl: Ctl,
+}
+
+impl Watcher {
+    // subscribing for ctl.read after polling
+    pub fn open(devname: &str) -> Result<Self, Error> {
+        let ctl = Ctl::new(devname, false).map_err(|source| Error::CardUnavailable { devname: devname.to_string(), source })?;
+        ctl.subscribe_events(true)
+            .map_err(|source| Error::SubscribeFailed { devname: devname.to_string(), hint: access_hint(&ctl, &source), source })?;
+        Ok(Watcher { ctl })
+    }
+
+    // polling the ctl along with the self-pipe of the signal thread, None timeout = infinite
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<Ready> {
+        wait_ready(&self.ctl, timeout)
+    }
+
+    // None = spurious wake-up
+    pub fn read(&self) -> Result<Option<CtlEvent>> {
+        Ok(self.ctl.read()?.map(|event| {
+            let mask = event.get_mask();
+            CtlEvent {
+                numid: event.get_id().get_numid(),
+                index: event.get_id().get_index(),
+                mask: mask.0,
+                value: mask.value(),
+                tlv: mask.tlv(),
+            }
+        }))
+    }
+}
+
+// card unbound, e.g. by the UDC rebind
+pub fn card_gone(err: &anyhow::Error, devname: &str) -> bool {
+    match err.chain().find_map(|cause| cause.downcast_ref::<alsa::Error>()) {
+        Some(err) if err.errno() == Errno::ENODEV => true,
+        // the alsa crate does not keep negative error codes, probing the card instead
+        Some(_) => Ctl::new(devname, false).is_err(),
+        None => false,
+    }
+}
+
+fn wait_ready(ctl: &Ctl, timeout: Option<Duration>) -> Result<Ready> {
+    let mut fds = ctl.get()?;
+    fds.push(signals::wake_pollfd());
+    alsa::poll::poll(&mut fds, timeout.map_or(-1, |timeout| timeout.as_millis() as i32))?;
+    let (ctl_fds, wake_fds) = fds.split_at(fds.len() - 1);
+    // shutdown takes precedence over pending events
+    if wake_fds[0].revents != 0 {
+        return Ok(Ready::Wake(signals::read_wake()?));
+    }
+    match ctl.revents(ctl_fds)?.is_empty() {
+        true => Ok(Ready::Timeout),
+        false => Ok(Ready::Ctl),
+    }
+}
+
+// control device not accessible for reading and writing
+fn access_hint(ctl: &Ctl, err: &alsa::Error) -> String {
+    let path = match ctl.card_info() {
+        Ok(info) => format!("/dev/snd/controlC{}", info.get_card().get_index()),
+        Err(_) => return String::new(),
+    };
+    let denied = matches!(err.errno(), Errno::EACCES | Errno::EPERM)
+        || access(path.as_str(), AccessFlags::R_OK | AccessFlags::W_OK).is_err();
+    match denied {
+        true => format!(" (no read/write access to {}, check its permissions or the audio group membership)", path),
+        false => String::new(),
+    }
+}
+
+// coords = (device, subdevice) of the ctl
+pub fn get_ctl_data<'a>(h: &'a HCtl, devname: &str, elem_names: &str, coords: (u32, u32), value_index: u32,
+                    mapping: RateMapping, enum_rate_map: &HashMap<String, usize>) -> Result<Option<CtlData<'a>>> {
+    return match find_elem(elem_names, coords, h)? {
+        Some((elem_name, elem, id)) => {
+            if elem_name != elem_names {
+                info!("Ctl candidate '{}' of '{}' matched", elem_name, elem_names);
+            }
+            let numid = id.get_numid();
+            debug!("{} at device {} subdevice {} id {}", elem_name, coords.0, coords.1, numid);
+            let info = elem.info()?;
+            if value_index >= info.get_count() {
+                return Err(anyhow!("Ctl '{}' has {} values, index {} out of range", elem_name, info.get_count(), value_index));
+            }
+            let elem_type = info.get_type();
+            let enum_rates = match elem_type {
+                ElemType::Integer | ElemType::Boolean => None,
+                ElemType::Enumerated => Some(ctl_enum::get_item_rates(devname, numid, enum_rate_map)?),
+                _ => {
+                    return Err(Error::UnsupportedCtlType { name: elem_name.to_string(), elem_type: format!("{:?}", elem_type) }.into());
+                }
+            };
+            let boolean = elem_type == ElemType::Boolean;
+            if boolean && matches!(mapping, RateMapping::Direct) {
+                return Err(Error::MissingFixedRate(elem_name.to_string()).into());
+            }
+            debug!("{} value mapping: {:?}", elem_name, mapping);
+            let elem_index = id.get_index();
+            Ok(Some(CtlData { name: elem_name.to_string(), elem, numid, elem_index, value_index, enum_rates, boolean, mapping, secondary: None }))
+        }
+        None => Ok(None)
+    };
+}
+
+// secondary ctl with the same value index and mapping as the primary one
+pub fn attach_secondary<'a>(ctl_data: &mut CtlData<'a>, h: &'a HCtl, devname: &str, name: &str, coords: (u32, u32),
+                        enum_rate_map: &HashMap<String, usize>) -> Result<()> {
+    match get_ctl_data(h, devname, name, coords, ctl_data.value_index, ctl_data.mapping, enum_rate_map)? {
+        Some(secondary) => {
+            info!("Ctl '{}' num ID {} is secondary to ctl '{}'", secondary.name, secondary.numid, ctl_data.name);
+            ctl_data.secondary = Some(Box::new(secondary));
+        }
+        None => warn!("Secondary ctl '{}' not found, only ctl '{}' will be used", name, ctl_data.name),
+    }
+    Ok(())
+}
+
+// first existing ctl of the comma-separated candidate names, skipping candidates with unreadable ID
+pub fn find_elem<'a, 'n>(elemnames: &'n str, coords: (u32, u32), h: &'a HCtl)
+                     -> Result<Option<(&'n str, Elem<'a>, ElemId)>> {
+    let names: Vec<&str> = elemnames.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+    for name in &names {
+        let elem = match get_elem(name, coords, h)? {
+            Some(elem) => elem,
+            None => continue,
+        };
+        match elem.get_id() {
+            Ok(id) => return Ok(Some((name, elem, id))),
+            Err(err) if names.len() > 1 => warn!("Ctl candidate '{}' has unreadable ID, skipping, error: {}", name, err),
+            Err(err) => return Err(anyhow::Error::from(err).context(format!("Cannot read ID of ctl '{}'", name))),
+        }
+    }
+    Ok(None)
+}
+
+fn get_elem<'a>(elemname: &str, (device, subdevice): (u32, u32), h: &'a HCtl) -> Result<Option<Elem<'a>>> {
+    let mut elid = ElemId::new(ElemIface::PCM);
+    elid.set_device(device);
+    elid.set_subdevice(subdevice);
+    elid.set_name(&CString::new(elemname)?);
+    let elem = h.find_elem(&elid);
+    Ok(elem)
+}
+
+// the nonzero rate of the primary and the secondary ctl
+pub fn read_rate(ctl_data: &CtlData) -> Result<usize> {
+    let rate = read_ctl_rate(ctl_data)?;
+    match &ctl_data.secondary {
+        Some(secondary) => Ok(pick_rate(&ctl_data.name, rate, &secondary.name, read_ctl_rate(secondary)?)),
+        None => Ok(rate),
+    }
+}
+
+// the nonzero one, the primary rate when both differ, a transient disagreement must not end the loop
+fn pick_rate(name: &str, rate: usize, secondary_name: &str, secondary: usize) -> usize {
+    match (rate, secondary) {
+        (0, rate) | (rate, 0) => rate,
+        (rate, secondary) if rate == secondary => rate,
+        (rate, secondary) => {
+            warn!("Ctls '{}' and '{}' report different rates {} and {}, using {}", name, secondary_name, rate, secondary, rate);
+            rate
+        }
+    }
+}
+
+// rate already set when starting, None = stopped or unreadable, not worth ending the daemon
+pub fn initial_rate(name: &str, read: Result<usize>) -> Option<usize> {
+    match read {
+        Ok(0) => None,
+        Ok(rate) => Some(rate),
+        Err(err) => {
+            warn!("Cannot read the initial rate of ctl '{}', waiting for its change, error: {:#}", name, err);
+            None
+        }
+    }
+}
+
+// rate re-read without an event, None = unreadable, retried by the next reconcile
+// only the removed card ends the loop, to wait for its re-appearance
+pub fn reconciled_rate(name: &str, read: Result<usize>, devname: &str) -> Result<Option<usize>> {
+    match read {
+        Ok(rate) => Ok(Some(rate)),
+        Err(err) if card_gone(&err, devname) => Err(err),
+        Err(err) => {
+            warn!("Cannot re-read ctl '{}', error: {:#}", name, err);
+            Ok(None)
+        }
+    }
+}
+
+fn read_ctl_rate(ctl_data: &CtlData) -> Result<usize> {
+    let value = ctl_data.elem.read()?;
+    let raw = match &ctl_data.enum_rates {
+        // no selection or invalid item = rate 0
+        Some(rates) => value.get_enumerated(ctl_data.value_index).and_then(|item| rates.get(item as usize)).copied().unwrap_or(0),
+        None if ctl_data.boolean => value.get_boolean(ctl_data.value_index).unwrap_or(false) as usize,
+        // negative value = rate 0
+        None => value.get_integer(ctl_data.value_index).unwrap().max(0) as usize,
+    };
+    Ok(ctl_data.mapping.normalize(raw))
+}
+
+// event of the ctl or of its secondary
+pub fn fits_ctl(ctl_data: &CtlData, numid: u32, index: u32) -> bool {
+    (ctl_data.numid == numid && ctl_data.elem_index == index)
+        || ctl_data.secondary.as_ref().is_some_and(|secondary| fits_ctl(secondary, numid, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonzero_rate_of_two_ctls_picked() {
+        assert_eq!(pick_rate("Playback Rate", 0, "Playback Rate 2", 48000), 48000);
+        assert_eq!(pick_rate("Playback Rate", 44100, "Playback Rate 2", 0), 44100);
+        assert_eq!(pick_rate("Playback Rate", 0, "Playback Rate 2", 0), 0);
+        assert_eq!(pick_rate("Playback Rate", 96000, "Playback Rate 2", 96000), 96000);
+        // disagreeing ctls, the primary one wins
+        assert_eq!(pick_rate("Playback Rate", 48000, "Playback Rate 2", 44100), 48000);
+        assert_eq!(pick_rate("Playback Rate", 44100, "Playback Rate 2", 48000), 44100);
+    }
+
+    #[test]
+    fn initial_rate_skips_stopped_and_unreadable() {
+        assert_eq!(initial_rate("Playback Rate", Ok(48000)), Some(48000));
+        assert_eq!(initial_rate("Playback Rate", Ok(0)), None);
+        assert_eq!(initial_rate("Playback Rate", Err(anyhow!("Ctls report different rates"))), None);
+    }
+
+    #[test]
+    fn reconcile_skips_unreadable() {
+        assert_eq!(reconciled_rate("Playback Rate", Ok(48000), "hw:0").unwrap(), Some(48000));
+        assert_eq!(reconciled_rate("Playback Rate", Err(anyhow!("Other failure")), "hw:0").unwrap(), None);
+        let gone = anyhow::Error::from(alsa::Error::new("snd_ctl_elem_read", Errno::ENODEV as i32));
+        assert!(reconciled_rate("Playback Rate", Err(gone), "hw:0").is_err());
+    }
+
+    #[test]
+    fn differing_rates_use_primary() {
+        assert_eq!(pick_rate("Playback Rate", 0, "Rate2", 44100), 44100);
+        assert_eq!(pick_rate("Playback Rate", 48000, "Rate2", 0), 48000);
+        assert_eq!(pick_rate("Playback Rate", 48000, "Rate2", 44100), 48000);
+    }
+
+    #[test]
+    fn enodev_means_card_gone() {
+        let err = anyhow::Error::from(alsa::Error::new("snd_ctl_read", Errno::ENODEV as i32));
+        assert!(card_gone(&err.context("Reading ctl event"), "hw:0"));
+        let err = anyhow::Error::from(alsa::Error::new("snd_ctl_read", -(Errno::ENODEV as i32)));
+        assert!(card_gone(&err, "hw:NoSuchCard"));
+        assert!(!card_gone(&anyhow!("Other failure"), "hw:NoSuchCard"));
+    }
+}